@@ -0,0 +1,86 @@
+use crate::error::Error;
+use bson::Bson;
+use bson::Document;
+use sha2::Digest;
+use sha2::Sha256;
+
+/// An order-independent hash of a set of documents. It is the sum of the SHA-256 of every
+/// document, so the same documents give the same hash whatever order they are read in.
+#[derive(Debug, Default)]
+pub struct Checksum {
+    sum: u128,
+    count: i64,
+}
+
+impl Checksum {
+    pub fn add(&mut self, doc: &Document) -> Result<(), Error> {
+        let bytes = bson::to_vec(&canonical(doc))?;
+        let digest = Sha256::digest(&bytes);
+        let mut head = [0_u8; 16];
+        head.copy_from_slice(&digest[..16]);
+        self.sum = self.sum.wrapping_add(u128::from_be_bytes(head));
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn count(&self) -> i64 {
+        self.count
+    }
+
+    pub fn hash(&self) -> String {
+        format!("{:032x}", self.sum)
+    }
+}
+
+/// The document with its fields sorted by name at every level, so field order doesn't
+/// change the hash. Types still do.
+fn canonical(doc: &Document) -> Document {
+    let mut fields: Vec<_> = doc.iter().collect();
+    fields.sort_by(|a, b| a.0.cmp(b.0));
+    fields
+        .into_iter()
+        .map(|(k, v)| (k.clone(), canonical_value(v)))
+        .collect()
+}
+
+fn canonical_value(v: &Bson) -> Bson {
+    match v {
+        Bson::Document(d) => Bson::Document(canonical(d)),
+        Bson::Array(a) => Bson::Array(a.iter().map(canonical_value).collect()),
+        v => v.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bson::doc;
+
+    fn checksum(docs: &[Document]) -> Checksum {
+        let mut sum = Checksum::default();
+        for d in docs {
+            sum.add(d).unwrap();
+        }
+        sum
+    }
+
+    #[test]
+    fn order_independent() {
+        let a = doc! { "_id": 1, "name": "martin", "address": { "city": "Stockholm", "zip": 1 } };
+        let b = doc! { "_id": 2, "tags": [{ "y": 1, "x": 2 }] };
+        let sum = checksum(&[a.clone(), b.clone()]);
+        assert_eq!(sum.count(), 2);
+        assert_eq!(sum.hash().len(), 32);
+        assert_eq!(sum.hash(), checksum(&[b.clone(), a]).hash());
+
+        // field order doesn't matter, but values and types do.
+        let a2 = doc! { "name": "martin", "address": { "zip": 1, "city": "Stockholm" }, "_id": 1 };
+        assert_eq!(sum.hash(), checksum(&[b.clone(), a2]).hash());
+        let a3 =
+            doc! { "_id": 1, "name": "martin", "address": { "city": "Stockholm", "zip": 1_i64 } };
+        assert_ne!(sum.hash(), checksum(&[b.clone(), a3]).hash());
+
+        // duplicates don't cancel out.
+        assert_ne!(checksum(&[b.clone(), b]).hash(), Checksum::default().hash());
+    }
+}
@@ -0,0 +1,233 @@
+use crate::error::Error;
+use crate::output::OutputMode;
+use mongodb::options::AuthMechanism;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const CONFIG_ENV_VAR: &str = "MUNG_CONFIG";
+
+/// The config file, by default `~/.config/mung/config.toml`.
+///
+/// ```toml
+/// default = "local"
+///
+/// [profiles.local]
+/// url = "mongodb://127.0.0.1:27017"
+/// db = "test"
+///
+/// [profiles.prod]
+/// url = "mongodb+srv://dbUser@clusterx-abc123.mongodb.net"
+/// db = "production"
+/// password_command = "pass show mongo/prod"
+/// read_only = true
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Profile to use when none is given with `-p`.
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub db: Option<String>,
+    /// Use the database in the URL when `db` isn't given, like `--url-db`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub url_db: bool,
+    /// Prompt for the password, like `-W`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub password_prompt: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_command: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_auth_mechanism",
+        serialize_with = "serialize_auth_mechanism"
+    )]
+    pub auth_mechanism: Option<AuthMechanism>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsSettings>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub compact: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputMode>,
+    /// Refuse any command that writes.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub read_only: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_key_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub allow_invalid_hostnames: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub allow_invalid_certificates: bool,
+}
+
+fn is_false(v: &bool) -> bool {
+    !*v
+}
+
+fn deserialize_auth_mechanism<'de, D>(d: D) -> Result<Option<AuthMechanism>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(d)?;
+    s.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+fn serialize_auth_mechanism<S>(v: &Option<AuthMechanism>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match v {
+        Some(m) => s.serialize_str(m.as_str()),
+        None => s.serialize_none(),
+    }
+}
+
+impl Config {
+    /// Reads the config file. A missing file is the same as an empty config,
+    /// unless it was explicitly pointed out with `MUNG_CONFIG`.
+    pub fn load() -> Result<Config, Error> {
+        let (path, explicit) = match std::env::var_os(CONFIG_ENV_VAR) {
+            Some(p) => (PathBuf::from(p), true),
+            None => match default_path() {
+                Some(p) => (p, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        if !explicit && !path.exists() {
+            trace!("No config file: {}", path.display());
+            return Ok(Config::default());
+        }
+
+        debug!("Read config: {}", path.display());
+        let content = std::fs::read_to_string(&path)?;
+        Config::parse(&content).map_err(|e| Error::Usage(format!("{}: {}", path.display(), e)))
+    }
+
+    fn parse(content: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(content).map_err(|e| e.to_string())?;
+        if let Some(name) = &config.default {
+            if !config.profiles.contains_key(name) {
+                return Err(format!("Default profile not found: {}", name));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The profile selected with `-p`, or the default profile if there is one.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<Profile>, Error> {
+        let name = match name.or(self.default.as_deref()) {
+            Some(n) => n,
+            None => return Ok(None),
+        };
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| Error::Usage(format!("Profile not found: {}", name)))?;
+        debug!("Use profile: {}", name);
+        Ok(Some(profile.clone()))
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(d) if !d.is_empty() => PathBuf::from(d),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("mung").join("config.toml"))
+}
+
+/// Hides any password in a connection URL, for listing profiles.
+pub fn redact_url(url: &str) -> String {
+    let (scheme, rest) = match url.split_once("://") {
+        Some(v) => v,
+        None => return url.to_string(),
+    };
+    let (auth, host) = match rest.split_once('@') {
+        Some(v) => v,
+        None => return url.to_string(),
+    };
+    match auth.split_once(':') {
+        Some((user, _)) => format!("{}://{}:***@{}", scheme, user, host),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_profiles() {
+        let config = Config::parse(
+            r#"
+            default = "local"
+
+            [profiles.local]
+            url = "mongodb://127.0.0.1:27017"
+
+            [profiles.prod]
+            url = "mongodb+srv://dbUser@clusterx-abc123.mongodb.net"
+            db = "production"
+            password_command = "pass show mongo/prod"
+            auth_mechanism = "SCRAM-SHA-256"
+            read_only = true
+
+            [profiles.prod.tls]
+            ca_file = "/etc/ssl/prod-ca.pem"
+            "#,
+        )
+        .unwrap();
+
+        let local = config.profile(None).unwrap().unwrap();
+        assert_eq!(local.url.as_deref(), Some("mongodb://127.0.0.1:27017"));
+        assert!(!local.read_only);
+
+        let prod = config.profile(Some("prod")).unwrap().unwrap();
+        assert_eq!(prod.db.as_deref(), Some("production"));
+        assert!(prod.read_only);
+        assert_eq!(prod.auth_mechanism, Some(AuthMechanism::ScramSha256));
+        assert!(prod.tls.unwrap().ca_file.is_some());
+
+        assert!(config.profile(Some("staging")).is_err());
+        assert!(Config::parse("default = \"nope\"").is_err());
+        assert!(Config::parse("[profiles.x]\nuri = \"mongodb://x\"").is_err());
+        assert!(Config::parse("[profiles.x]\nauth_mechanism = \"NOPE\"").is_err());
+    }
+
+    #[test]
+    fn redact() {
+        assert_eq!(
+            redact_url("mongodb://u:secret@h/db"),
+            "mongodb://u:***@h/db"
+        );
+        assert_eq!(redact_url("mongodb://u@h"), "mongodb://u@h");
+        assert_eq!(redact_url("mongodb://h"), "mongodb://h");
+    }
+}
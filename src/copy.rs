@@ -0,0 +1,295 @@
+use crate::error::Error;
+use crate::import::{self, Import, ImportFormat, ImportOpts};
+use crate::{
+    connect_url, decode_bson, follow_cursor, read_cursor, run_command, warn_fallback,
+    with_max_time, with_write_concern, write_doc, Context, Opts,
+};
+use bson::doc;
+use bson::Bson;
+use bson::Document;
+use mongodb::options::AggregateOptions;
+use mongodb::options::FindOneOptions;
+use mongodb::sync::Client;
+use serde_json::Value;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct CopyOpts {
+    /// Collection to copy
+    pub collection: String,
+
+    /// URL of the source, instead of the connection given by -u or the profile
+    #[structopt(long)]
+    pub from_url: Option<String>,
+
+    /// Source database [default: the database given by -d]
+    #[structopt(long)]
+    pub from_db: Option<String>,
+
+    /// URL of the target, instead of the connection given by -u or the profile
+    #[structopt(long)]
+    pub to_url: Option<String>,
+
+    /// Target database [default: the database given by -d]
+    #[structopt(long)]
+    pub to_db: Option<String>,
+
+    /// Target collection [default: the source collection]
+    #[structopt(long)]
+    pub to_collection: Option<String>,
+
+    /// Filter of the documents to copy, like { active: true }
+    #[structopt(long)]
+    pub query: Option<String>,
+
+    /// Aggregation stage, or array of stages, to run on every document before inserting it,
+    /// like { $unset: "email" }
+    #[structopt(long)]
+    pub transform: Option<String>,
+
+    /// Create the indexes of the source collection on the target
+    #[structopt(long)]
+    pub indexes: bool,
+
+    /// Drop the target collection first
+    #[structopt(long)]
+    pub drop: bool,
+
+    /// Carry on from the highest _id in the target, after an interrupted copy
+    #[structopt(long)]
+    pub resume: bool,
+
+    /// Documents per insert command
+    #[structopt(long, default_value = "1000")]
+    pub batch_size: usize,
+}
+
+impl CopyOpts {
+    /// Whether the target is on the connection given by -u or the profile.
+    pub fn writes_main(&self) -> bool {
+        self.to_url.is_none()
+    }
+}
+
+/// Streams documents from a source cursor into batched inserts on the target.
+pub fn handle_copy(
+    client: &Client,
+    copts: &CopyOpts,
+    opts: &Opts,
+    ctx: &Context,
+) -> Result<(), Error> {
+    check_copy(copts, opts)?;
+
+    let from_db = copts.from_db.as_deref().unwrap_or_else(|| opts.dbname());
+    let to_db = copts.to_db.as_deref().unwrap_or_else(|| opts.dbname());
+    let to_coll = copts.to_collection.as_deref().unwrap_or(&copts.collection);
+    if copts.to_db.is_none() && ctx.db_fallback {
+        warn_fallback();
+    }
+
+    let source = match &copts.from_url {
+        Some(url) => connect_url(url, opts, ctx)?,
+        None => client.clone(),
+    };
+    let target = match &copts.to_url {
+        Some(url) => connect_url(url, opts, ctx)?,
+        None => client.clone(),
+    };
+    let source_db = source.database(from_db);
+    let target_db = target.database(to_db);
+    let mut source_session = source.start_session(None)?;
+    let mut target_session = target.start_session(None)?;
+
+    if copts.drop {
+        import::drop_collection(&target_db, &mut target_session, to_coll, opts, ctx)?;
+    }
+
+    let mut n_indexes = 0;
+    if copts.indexes {
+        debug!("Call listIndexes: {}", copts.collection);
+        let res = run_command(
+            &source_db,
+            &mut source_session,
+            with_max_time(doc! { "listIndexes": &copts.collection }, opts),
+        )?;
+        let mut indexes = vec![];
+        follow_cursor(&source_db, &mut source_session, res, |mut index| {
+            if index.get_str("name") != Ok("_id_") {
+                index.remove("ns");
+                indexes.push(index);
+            }
+            Ok(())
+        })?;
+        n_indexes = indexes.len() as i64;
+        if !indexes.is_empty() {
+            debug!("Call createIndexes: {}", to_coll);
+            let cmd = doc! { "createIndexes": to_coll, "indexes": indexes };
+            run_command(
+                &target_db,
+                &mut target_session,
+                with_max_time(with_write_concern(cmd, opts, ctx)?, opts),
+            )?;
+        }
+    }
+
+    let mut pipeline = vec![];
+    if let Some(query) = &copts.query {
+        pipeline.push(doc! { "$match": decode_bson(query)? });
+    }
+    if copts.resume {
+        let sort = FindOneOptions::builder().sort(doc! { "_id": -1 }).build();
+        let last = target_db
+            .collection::<Document>(to_coll)
+            .find_one_with_session(None, sort, &mut target_session)?;
+        if let Some(id) = last.as_ref().and_then(|d| d.get("_id")) {
+            info!("Resume after _id: {}", id);
+            pipeline.push(doc! { "$match": { "_id": { "$gt": id } } });
+        }
+    }
+    // _id order is what makes it possible to resume.
+    pipeline.push(doc! { "$sort": { "_id": 1 } });
+    if let Some(transform) = &copts.transform {
+        pipeline.extend(parse_transform(transform)?);
+    }
+
+    debug!("Call aggregate: {:?}", pipeline);
+    let agg_opts = AggregateOptions::builder()
+        .max_time(opts.max_time)
+        .allow_disk_use(true)
+        .build();
+    let cursor = source_db
+        .collection::<Document>(&copts.collection)
+        .aggregate_with_session(pipeline, agg_opts, &mut source_session)?;
+
+    let iopts = ImportOpts {
+        collection: to_coll.to_string(),
+        format: ImportFormat::Json,
+        ordered: true,
+        upsert_fields: vec![],
+        drop: false,
+        batch_size: copts.batch_size,
+    };
+    let mut import = Import::new(&target_db, &mut target_session, &iopts, opts, ctx);
+    read_cursor(cursor, &mut source_session, |doc| import.push(doc))?;
+    let stats = import.finish()?;
+
+    write_doc(
+        &doc! { "nInserted": stats.inserted, "nIndexes": n_indexes },
+        opts,
+        ctx,
+    )?;
+
+    Ok(())
+}
+
+fn check_copy(copts: &CopyOpts, opts: &Opts) -> Result<(), Error> {
+    if copts.batch_size == 0 {
+        return Err(Error::Usage("--batch-size must be at least 1".into()));
+    }
+    if copts.drop && copts.resume {
+        return Err(Error::Usage(
+            "--drop can't be combined with --resume".into(),
+        ));
+    }
+    // a transform can change or drop _id, which is what --resume goes by.
+    if copts.resume && copts.transform.is_some() {
+        return Err(Error::Usage(
+            "--transform can't be combined with --resume".into(),
+        ));
+    }
+
+    // compare the URLs that get connected to, with the main URL in place of a missing one.
+    let url = opts.url();
+    let from_url = copts.from_url.as_deref().unwrap_or(&url);
+    let to_url = copts.to_url.as_deref().unwrap_or(&url);
+    let from_db = copts.from_db.as_deref().unwrap_or_else(|| opts.dbname());
+    let to_db = copts.to_db.as_deref().unwrap_or_else(|| opts.dbname());
+    let to_coll = copts.to_collection.as_deref().unwrap_or(&copts.collection);
+    if from_url == to_url && from_db == to_db && copts.collection == to_coll {
+        return Err(Error::Usage(
+            "Source and target are the same collection".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// A transform is one aggregation stage or an array of stages.
+fn parse_transform(s: &str) -> Result<Vec<Document>, Error> {
+    let json: Value = json5::from_str(s)?;
+    match bson::to_bson(&json)? {
+        Bson::Document(d) => Ok(vec![d]),
+        Bson::Array(a) => a
+            .into_iter()
+            .map(|s| match s {
+                Bson::Document(d) => Ok(d),
+                _ => Err(Error::Usage("--transform stages must be documents".into())),
+            })
+            .collect(),
+        _ => Err(Error::Usage(
+            "--transform must be a stage or an array of stages".into(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn transform() {
+        assert_eq!(
+            parse_transform("{ $unset: 'email' }").unwrap(),
+            vec![doc! { "$unset": "email" }]
+        );
+        assert_eq!(
+            parse_transform("[{ $unset: ['email'] }, { $set: { seeded: true } }]")
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(parse_transform("[1]").is_err());
+        assert!(parse_transform("'x'").is_err());
+    }
+
+    fn check(args: &[&str]) -> Result<(), Error> {
+        let opts = Opts::from_iter(args);
+        match &opts.sub {
+            Some(crate::Sub::Copy(copts)) => check_copy(copts, &opts),
+            _ => panic!("not a copy"),
+        }
+    }
+
+    #[test]
+    fn same_collection() {
+        assert!(check(&["mung", "copy", "users"]).is_err());
+        assert!(check(&["mung", "copy", "users", "--to-collection", "users2"]).is_ok());
+        assert!(check(&["mung", "copy", "users", "--to-url", "mongodb://other"]).is_ok());
+        assert!(check(&[
+            "mung",
+            "-u",
+            "mongodb://a",
+            "copy",
+            "users",
+            "--from-url",
+            "mongodb://a"
+        ])
+        .is_err());
+        assert!(check(&[
+            "mung",
+            "copy",
+            "users",
+            "--to-url",
+            "mongodb://127.0.0.1:27017"
+        ])
+        .is_err());
+        assert!(check(&["mung", "-d", "a", "copy", "users", "--to-db", "b"]).is_ok());
+    }
+
+    #[test]
+    fn resume() {
+        let args = ["mung", "copy", "users", "--to-db", "b", "--resume"];
+        assert!(check(&args).is_ok());
+        assert!(check(&[&args[..], &["--drop"]].concat()).is_err());
+        assert!(check(&[&args[..], &["--transform", "{ $unset: 'x' }"]].concat()).is_err());
+    }
+}
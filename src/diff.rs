@@ -0,0 +1,530 @@
+use crate::error::Error;
+use crate::output;
+use crate::{connect_url, decode_bson, write_doc, Context, Opts};
+use bson::doc;
+use bson::Bson;
+use bson::Document;
+use mongodb::options::FindOptions;
+use mongodb::sync::Client;
+use std::cmp::Ordering;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct DiffOpts {
+    /// Collection to compare
+    pub collection: String,
+
+    /// URL of the left side, instead of the connection given by -u or the profile
+    #[structopt(long)]
+    pub left_url: Option<String>,
+
+    /// Left database [default: the database given by -d]
+    #[structopt(long)]
+    pub left_db: Option<String>,
+
+    /// URL of the right side, instead of the connection given by -u or the profile
+    #[structopt(long)]
+    pub right_url: Option<String>,
+
+    /// Right database [default: the database given by -d]
+    #[structopt(long)]
+    pub right_db: Option<String>,
+
+    /// Right collection [default: the left collection]
+    #[structopt(long)]
+    pub right_collection: Option<String>,
+
+    /// Comma separated fields that identify a document on both sides
+    #[structopt(long, use_delimiter = true, default_value = "_id")]
+    pub key: Vec<String>,
+
+    /// Filter of the documents to compare, like { active: true }
+    #[structopt(long)]
+    pub query: Option<String>,
+}
+
+/// Compares two collections by streaming both sides sorted by key, and writes a record for
+/// every document that differs, followed by the counts.
+pub fn handle_diff(
+    client: &Client,
+    dopts: &DiffOpts,
+    opts: &Opts,
+    ctx: &Context,
+) -> Result<(), Error> {
+    let left_client = match &dopts.left_url {
+        Some(url) => connect_url(url, opts, ctx)?,
+        None => client.clone(),
+    };
+    let right_client = match &dopts.right_url {
+        Some(url) => connect_url(url, opts, ctx)?,
+        None => client.clone(),
+    };
+    let left_db = dopts.left_db.as_deref().unwrap_or_else(|| opts.dbname());
+    let right_db = dopts.right_db.as_deref().unwrap_or_else(|| opts.dbname());
+    let right_coll = dopts
+        .right_collection
+        .as_deref()
+        .unwrap_or(&dopts.collection);
+
+    let filter = dopts.query.as_deref().map(decode_bson).transpose()?;
+    let mut sort = Document::new();
+    for k in &dopts.key {
+        sort.insert(k.clone(), 1);
+    }
+    let find_opts = |client: &Client| -> Result<FindOptions, Error> {
+        let mut find_opts = FindOptions::builder()
+            .sort(sort.clone())
+            .max_time(opts.max_time)
+            .build();
+        if supports_disk_use(client)? {
+            find_opts.allow_disk_use = Some(true);
+        }
+        Ok(find_opts)
+    };
+
+    let mut left_session = left_client.start_session(None)?;
+    let mut left_cursor = left_client
+        .database(left_db)
+        .collection::<Document>(&dopts.collection)
+        .find_with_session(filter.clone(), find_opts(&left_client)?, &mut left_session)?;
+    let mut right_session = right_client.start_session(None)?;
+    let mut right_cursor = right_client
+        .database(right_db)
+        .collection::<Document>(right_coll)
+        .find_with_session(filter, find_opts(&right_client)?, &mut right_session)?;
+
+    let left = std::iter::from_fn(|| {
+        left_cursor
+            .next(&mut left_session)
+            .map(|r| r.map_err(Error::from))
+    });
+    let right = std::iter::from_fn(|| {
+        right_cursor
+            .next(&mut right_session)
+            .map(|r| r.map_err(Error::from))
+    });
+
+    let mut counts = Counts::default();
+    let res = merge(left, right, &dopts.key, &mut counts, |d| {
+        write_doc(&d, opts, ctx)
+    });
+
+    let mut summary = doc! {
+        "type": "summary",
+        "same": counts.same,
+        "changed": counts.changed,
+        "onlyLeft": counts.only_left,
+        "onlyRight": counts.only_right,
+    };
+    if let Err(e) = res {
+        // the counts only cover the documents before the failure, which the summary says.
+        summary.insert("incomplete", true);
+        let _ = write_doc(&summary, opts, ctx);
+        return Err(e);
+    }
+    write_doc(&summary, opts, ctx)?;
+
+    if counts.changed + counts.only_left + counts.only_right > 0 {
+        return Err(Error::Failed("Collections differ".into()));
+    }
+    Ok(())
+}
+
+/// allowDiskUse on find needs 4.4, which is wire version 9, and older servers reject it.
+fn supports_disk_use(client: &Client) -> Result<bool, Error> {
+    let res = client
+        .database("admin")
+        .run_command(doc! { "isMaster": 1 }, None)?;
+    let version = match res.get("maxWireVersion") {
+        Some(Bson::Int32(v)) => *v as i64,
+        Some(Bson::Int64(v)) => *v,
+        _ => 0,
+    };
+    Ok(version >= 9)
+}
+
+/// Walks both sides in key order, counts every document and hands a record for every one
+/// that differs to `emit`. The counts are kept up to date when it fails halfway.
+fn merge<L, R, F>(
+    left: L,
+    right: R,
+    keys: &[String],
+    counts: &mut Counts,
+    mut emit: F,
+) -> Result<(), Error>
+where
+    L: Iterator<Item = Result<Document, Error>>,
+    R: Iterator<Item = Result<Document, Error>>,
+    F: FnMut(Document) -> Result<(), Error>,
+{
+    let mut left = Side::new("left", left, keys);
+    let mut right = Side::new("right", right, keys);
+    let mut l = left.next()?;
+    let mut r = right.next()?;
+    loop {
+        let order = match (&l, &r) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((lk, _)), Some((rk, _))) => compare_keys(lk, rk),
+        };
+        match order {
+            Ordering::Less => {
+                let (key, doc) = l.take().unwrap_or_default();
+                counts.only_left += 1;
+                emit(doc! { "type": "onlyLeft", "key": key_doc(keys, key), "doc": doc })?;
+                l = left.next()?;
+            }
+            Ordering::Greater => {
+                let (key, doc) = r.take().unwrap_or_default();
+                counts.only_right += 1;
+                emit(doc! { "type": "onlyRight", "key": key_doc(keys, key), "doc": doc })?;
+                r = right.next()?;
+            }
+            Ordering::Equal => {
+                let (key, ldoc) = l.take().unwrap_or_default();
+                let (_, rdoc) = r.take().unwrap_or_default();
+                let mut diff = vec![];
+                diff_docs("", &ldoc, &rdoc, &mut diff);
+                if diff.is_empty() {
+                    counts.same += 1;
+                } else {
+                    counts.changed += 1;
+                    emit(doc! { "type": "changed", "key": key_doc(keys, key), "diff": diff })?;
+                }
+                l = left.next()?;
+                r = right.next()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    same: i64,
+    changed: i64,
+    only_left: i64,
+    only_right: i64,
+}
+
+/// One side of the diff, which checks that the documents really come in key order, since
+/// the merge relies on it.
+struct Side<'a, I> {
+    name: &'static str,
+    docs: I,
+    keys: &'a [String],
+    last: Option<Vec<Bson>>,
+}
+
+impl<'a, I: Iterator<Item = Result<Document, Error>>> Side<'a, I> {
+    fn new(name: &'static str, docs: I, keys: &'a [String]) -> Self {
+        Side {
+            name,
+            docs,
+            keys,
+            last: None,
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<(Vec<Bson>, Document)>, Error> {
+        let doc = match self.docs.next() {
+            Some(doc) => doc?,
+            None => return Ok(None),
+        };
+        let key: Vec<Bson> = self
+            .keys
+            .iter()
+            .map(|k| output::lookup(&doc, k).unwrap_or(Bson::Null))
+            .collect();
+        if let Some(last) = &self.last {
+            if compare_keys(last, &key) != Ordering::Less {
+                return Err(Error::Usage(format!(
+                    "The {} side has a duplicate or unordered key {:?}, the key must be unique \
+                     and not an array",
+                    self.name, key
+                )));
+            }
+        }
+        self.last = Some(key.clone());
+        Ok(Some((key, doc)))
+    }
+}
+
+fn key_doc(keys: &[String], values: Vec<Bson>) -> Document {
+    keys.iter().cloned().zip(values).collect()
+}
+
+fn compare_keys(a: &[Bson], b: &[Bson]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| compare_bson(x, y))
+        .find(|o| *o != Ordering::Equal)
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+/// The order of the BSON types when the server sorts values of different types.
+fn type_rank(b: &Bson) -> u8 {
+    match b {
+        Bson::MinKey => 1,
+        Bson::Null | Bson::Undefined => 2,
+        Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Decimal128(_) => 3,
+        Bson::String(_) | Bson::Symbol(_) => 4,
+        Bson::Document(_) => 5,
+        Bson::Array(_) => 6,
+        Bson::Binary(_) => 7,
+        Bson::ObjectId(_) => 8,
+        Bson::Boolean(_) => 9,
+        Bson::DateTime(_) => 10,
+        Bson::Timestamp(_) => 11,
+        Bson::RegularExpression(_) => 12,
+        Bson::DbPointer(_) => 13,
+        Bson::JavaScriptCode(_) => 14,
+        Bson::JavaScriptCodeWithScope(_) => 15,
+        Bson::MaxKey => 16,
+    }
+}
+
+fn to_f64(b: &Bson) -> f64 {
+    match b {
+        Bson::Int32(v) => *v as f64,
+        Bson::Int64(v) => *v as f64,
+        Bson::Double(v) => *v,
+        Bson::Decimal128(v) => v.to_string().parse().unwrap_or(f64::NAN),
+        _ => f64::NAN,
+    }
+}
+
+/// Orders values like the server does with the simple collation, as far as key values go.
+fn compare_bson(a: &Bson, b: &Bson) -> Ordering {
+    let rank = type_rank(a).cmp(&type_rank(b));
+    if rank != Ordering::Equal {
+        return rank;
+    }
+    match (a, b) {
+        (Bson::Int32(_) | Bson::Int64(_), Bson::Int32(_) | Bson::Int64(_)) => {
+            let int = |b: &Bson| match b {
+                Bson::Int32(v) => *v as i64,
+                Bson::Int64(v) => *v,
+                _ => 0,
+            };
+            int(a).cmp(&int(b))
+        }
+        (Bson::String(_) | Bson::Symbol(_), _) => {
+            let s = |b: &Bson| match b {
+                Bson::String(s) | Bson::Symbol(s) => s.clone(),
+                _ => String::new(),
+            };
+            s(a).cmp(&s(b))
+        }
+        (Bson::Document(x), Bson::Document(y)) => x
+            .iter()
+            .zip(y.iter())
+            .map(|((kx, vx), (ky, vy))| {
+                type_rank(vx)
+                    .cmp(&type_rank(vy))
+                    .then_with(|| kx.cmp(ky))
+                    .then_with(|| compare_bson(vx, vy))
+            })
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or_else(|| x.len().cmp(&y.len())),
+        (Bson::Array(x), Bson::Array(y)) => compare_keys(x, y),
+        (Bson::Binary(x), Bson::Binary(y)) => x
+            .bytes
+            .len()
+            .cmp(&y.bytes.len())
+            .then_with(|| u8::from(x.subtype).cmp(&u8::from(y.subtype)))
+            .then_with(|| x.bytes.cmp(&y.bytes)),
+        (Bson::ObjectId(x), Bson::ObjectId(y)) => x.bytes().cmp(&y.bytes()),
+        (Bson::Boolean(x), Bson::Boolean(y)) => x.cmp(y),
+        (Bson::DateTime(x), Bson::DateTime(y)) => x.cmp(y),
+        (Bson::Timestamp(x), Bson::Timestamp(y)) => {
+            (x.time, x.increment).cmp(&(y.time, y.increment))
+        }
+        (Bson::RegularExpression(x), Bson::RegularExpression(y)) => x
+            .pattern
+            .cmp(&y.pattern)
+            .then_with(|| x.options.cmp(&y.options)),
+        _ if type_rank(a) == 3 => {
+            // NaN sorts before all other numbers.
+            let (x, y) = (to_f64(a), to_f64(b));
+            match (x.is_nan(), y.is_nan()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                (false, false) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+            }
+        }
+        _ => Ordering::Equal,
+    }
+}
+
+/// Adds an entry with the dotted path and the values on either side for every field that
+/// differs. Field order doesn't matter, but types do, so `1` and `NumberLong(1)` differ.
+fn diff_docs(prefix: &str, left: &Document, right: &Document, out: &mut Vec<Document>) {
+    for (k, lv) in left {
+        diff_value(&join(prefix, k), Some(lv), right.get(k), out);
+    }
+    for (k, rv) in right {
+        if !left.contains_key(k) {
+            diff_value(&join(prefix, k), None, Some(rv), out);
+        }
+    }
+}
+
+fn diff_value(path: &str, left: Option<&Bson>, right: Option<&Bson>, out: &mut Vec<Document>) {
+    match (left, right) {
+        (Some(Bson::Document(l)), Some(Bson::Document(r))) => diff_docs(path, l, r, out),
+        (Some(Bson::Array(l)), Some(Bson::Array(r))) if l.len() == r.len() => {
+            for (i, (lv, rv)) in l.iter().zip(r).enumerate() {
+                diff_value(&join(path, &i.to_string()), Some(lv), Some(rv), out);
+            }
+        }
+        (l, r) if l == r => {}
+        (l, r) => {
+            let mut entry = doc! { "path": path };
+            if let Some(l) = l {
+                entry.insert("left", l.clone());
+            }
+            if let Some(r) = r {
+                entry.insert("right", r.clone());
+            }
+            out.push(entry);
+        }
+    }
+}
+
+fn join(prefix: &str, k: &str) -> String {
+    if prefix.is_empty() {
+        k.to_string()
+    } else {
+        format!("{}.{}", prefix, k)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bson::oid::ObjectId;
+
+    #[test]
+    fn order() {
+        let sorted = vec![
+            Bson::MinKey,
+            Bson::Null,
+            Bson::Double(f64::NAN),
+            Bson::Int32(-5),
+            Bson::Double(1.5),
+            Bson::Int64(2),
+            Bson::String("a".into()),
+            Bson::String("b".into()),
+            Bson::Document(doc! { "a": 1 }),
+            Bson::Document(doc! { "a": 1, "b": 1 }),
+            Bson::ObjectId(ObjectId::parse_str("5f5e0f000000000000000000").unwrap()),
+            Bson::ObjectId(ObjectId::parse_str("5f5e0f000000000000000001").unwrap()),
+            Bson::Boolean(false),
+            Bson::DateTime(bson::DateTime::from_millis(0)),
+            Bson::MaxKey,
+        ];
+        for w in sorted.windows(2) {
+            assert_eq!(compare_bson(&w[0], &w[1]), Ordering::Less, "{:?}", w);
+            assert_eq!(compare_bson(&w[1], &w[0]), Ordering::Greater, "{:?}", w);
+        }
+        assert_eq!(
+            compare_bson(&Bson::Int32(2), &Bson::Double(2.0)),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn field_diff() {
+        let left = doc! { "a": 1, "b": { "c": 1, "d": [1, 2] }, "e": "x", "same": true };
+        let right = doc! { "same": true, "a": 1_i64, "b": { "c": 1, "d": [1, 3] }, "f": 1 };
+        let mut out = vec![];
+        diff_docs("", &left, &right, &mut out);
+        assert_eq!(
+            out,
+            vec![
+                doc! { "path": "a", "left": 1, "right": 1_i64 },
+                doc! { "path": "b.d.1", "left": 2, "right": 3 },
+                doc! { "path": "e", "left": "x" },
+                doc! { "path": "f", "right": 1 },
+            ]
+        );
+
+        let mut out = vec![];
+        diff_docs(
+            "",
+            &doc! { "a": 1, "b": 2 },
+            &doc! { "b": 2, "a": 1 },
+            &mut out,
+        );
+        assert!(out.is_empty());
+    }
+
+    fn run_merge(
+        left: Vec<Document>,
+        right: Vec<Document>,
+    ) -> (Result<(), Error>, Counts, Vec<Document>) {
+        let keys = vec!["_id".to_string()];
+        let mut counts = Counts::default();
+        let mut out = vec![];
+        let res = merge(
+            left.into_iter().map(Ok),
+            right.into_iter().map(Ok),
+            &keys,
+            &mut counts,
+            |d| {
+                out.push(d);
+                Ok(())
+            },
+        );
+        (res, counts, out)
+    }
+
+    #[test]
+    fn merge_sides() {
+        let (res, counts, out) = run_merge(
+            vec![
+                doc! { "_id": 1, "a": 1 },
+                doc! { "_id": 2, "a": 1 },
+                doc! { "_id": 3 },
+            ],
+            vec![
+                doc! { "_id": 2, "a": 2 },
+                doc! { "_id": 3 },
+                doc! { "_id": 4 },
+            ],
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            out,
+            vec![
+                doc! { "type": "onlyLeft", "key": { "_id": 1 }, "doc": { "_id": 1, "a": 1 } },
+                doc! {
+                    "type": "changed",
+                    "key": { "_id": 2 },
+                    "diff": [{ "path": "a", "left": 1, "right": 2 }],
+                },
+                doc! { "type": "onlyRight", "key": { "_id": 4 }, "doc": { "_id": 4 } },
+            ]
+        );
+        assert_eq!(
+            (
+                counts.same,
+                counts.changed,
+                counts.only_left,
+                counts.only_right
+            ),
+            (1, 1, 1, 1)
+        );
+
+        let (res, counts, out) = run_merge(
+            vec![doc! { "_id": 1 }, doc! { "_id": 3 }, doc! { "_id": 2 }],
+            vec![doc! { "_id": 1 }],
+        );
+        assert!(matches!(res, Err(Error::Usage(_))));
+        assert_eq!((counts.same, counts.only_left), (1, 1));
+        assert_eq!(out.len(), 1);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::error::Error;
+use crate::import::{self, ImportFormat, ImportOpts};
+use crate::input::DocReader;
+use crate::input::InputMode;
+use crate::output::{self, OutputMode};
+use crate::{
+    follow_cursor, read_cursor, run_command, with_max_time, with_write_concern, write_doc, Context,
+    Opts, INSERT_BATCH,
+};
+use bson::doc;
+use bson::Bson;
+use bson::Document;
+use mongodb::options::FindOptions;
+use mongodb::sync::ClientSession;
+use mongodb::sync::Database;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Collection names, types, options and indexes of a dump.
+const METADATA_FILE: &str = "metadata.json";
+
+#[derive(StructOpt, Debug)]
+pub struct DumpOpts {
+    /// Directory to write to, which is created if missing
+    #[structopt(parse(from_os_str))]
+    pub dir: PathBuf,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct RestoreOpts {
+    /// Directory written by dump
+    #[structopt(parse(from_os_str))]
+    pub dir: PathBuf,
+
+    /// Drop each collection before restoring it
+    #[structopt(long)]
+    pub drop: bool,
+}
+
+/// Writes every collection of the database as `<collection>.jsonl` in canonical extended
+/// JSON, and the collection options and indexes to `metadata.json`.
+pub fn handle_dump(
+    db: &Database,
+    session: &mut ClientSession,
+    dopts: &DumpOpts,
+    opts: &Opts,
+    ctx: &Context,
+) -> Result<(), Error> {
+    fs::create_dir_all(&dopts.dir)?;
+
+    debug!("Call listCollections");
+    let res = run_command(
+        db,
+        session,
+        with_max_time(doc! { "listCollections": 1 }, opts),
+    )?;
+    let mut colls = vec![];
+    follow_cursor(db, session, res, |c| {
+        colls.push(c);
+        Ok(())
+    })?;
+    colls.retain(|c| {
+        !c.get_str("name")
+            .unwrap_or("system.")
+            .starts_with("system.")
+    });
+    // sorted by name to make the metadata diffable.
+    colls.sort_by(|a, b| a.get_str("name").ok().cmp(&b.get_str("name").ok()));
+
+    let mut metadata = vec![];
+    for c in colls {
+        let name = c.get_str("name").unwrap_or_default();
+        let kind = c.get_str("type").unwrap_or("collection");
+        let mut entry = doc! {
+            "name": name,
+            "type": kind,
+            "options": c.get_document("options").cloned().unwrap_or_default(),
+        };
+        if kind != "view" {
+            entry.insert("indexes", list_indexes(db, session, name, opts)?);
+            let n = dump_collection(db, session, name, kind, &dopts.dir, opts)?;
+            write_doc(&doc! { "collection": name, "nDocuments": n }, opts, ctx)?;
+        }
+        metadata.push(entry);
+    }
+
+    let path = dopts.dir.join(METADATA_FILE);
+    debug!("Write metadata: {}", path.display());
+    let json = output::to_json(
+        &Bson::Document(doc! { "collections": metadata }),
+        OutputMode::EjsonCanonical,
+    );
+    let mut file = io::BufWriter::new(fs::File::create(&path)?);
+    serde_json::to_writer_pretty(&mut file, &json)?;
+    writeln!(file)?;
+    file.flush()?;
+
+    Ok(())
+}
+
+fn list_indexes(
+    db: &Database,
+    session: &mut ClientSession,
+    coll: &str,
+    opts: &Opts,
+) -> Result<Vec<Document>, Error> {
+    debug!("Call listIndexes: {}", coll);
+    let res = run_command(
+        db,
+        session,
+        with_max_time(doc! { "listIndexes": coll }, opts),
+    )?;
+    let mut indexes = vec![];
+    follow_cursor(db, session, res, |mut index| {
+        index.remove("ns");
+        indexes.push(index);
+        Ok(())
+    })?;
+    indexes.sort_by(|a, b| a.get_str("name").ok().cmp(&b.get_str("name").ok()));
+    Ok(indexes)
+}
+
+fn dump_collection(
+    db: &Database,
+    session: &mut ClientSession,
+    name: &str,
+    kind: &str,
+    dir: &Path,
+    opts: &Opts,
+) -> Result<i64, Error> {
+    let path = dir.join(data_file(name));
+    debug!("Dump {} to {}", name, path.display());
+    let mut file = io::BufWriter::new(fs::File::create(&path)?);
+
+    // _id order keeps the files diffable between dumps. Time series collections have no
+    // _id index to sort by.
+    let sort = if kind == "collection" {
+        Some(doc! { "_id": 1 })
+    } else {
+        None
+    };
+    let find_opts = FindOptions::builder()
+        .sort(sort)
+        .max_time(opts.max_time)
+        .build();
+    let coll = db.collection::<Document>(name);
+    let cursor = coll.find_with_session(None, find_opts, session)?;
+
+    let mut n = 0;
+    read_cursor(cursor, session, |doc| {
+        write_line(&mut file, doc)?;
+        n += 1;
+        Ok(())
+    })?;
+    file.flush()?;
+
+    info!("Dumped {} documents from {}", n, name);
+    Ok(n)
+}
+
+/// Writes a document as one line of canonical extended JSON, which keeps every BSON type.
+fn write_line<W: Write>(w: &mut W, doc: Document) -> Result<(), Error> {
+    let json = output::to_json(&Bson::Document(doc), OutputMode::EjsonCanonical);
+    serde_json::to_writer(&mut *w, &json)?;
+    writeln!(w)?;
+    Ok(())
+}
+
+/// The name of the data file of a collection. A collection name can have a `/`, or be `..`,
+/// so anything that isn't safe in a file name is percent encoded, as is a leading `.`.
+fn data_file(collection: &str) -> String {
+    let mut file = String::with_capacity(collection.len() + 6);
+    for (i, c) in collection.chars().enumerate() {
+        match c {
+            '.' if i == 0 => file.push_str("%2E"),
+            '%' | '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => {
+                file.push_str(&format!("%{:02X}", c as u32))
+            }
+            c if c.is_control() => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    file.push_str(&format!("%{:02X}", b));
+                }
+            }
+            c => file.push(c),
+        }
+    }
+    file.push_str(".jsonl");
+    file
+}
+
+/// Recreates the collections, views and indexes of a dump, and inserts the documents.
+pub fn handle_restore(
+    db: &Database,
+    session: &mut ClientSession,
+    ropts: &RestoreOpts,
+    opts: &Opts,
+    ctx: &Context,
+) -> Result<(), Error> {
+    let path = ropts.dir.join(METADATA_FILE);
+    debug!("Read metadata: {}", path.display());
+    let content = fs::read_to_string(&path)
+        .map_err(|e| Error::Usage(format!("{}: {}", path.display(), e)))?;
+    let json: serde_json::Value = serde_json::from_str(&content)?;
+    let colls = match Bson::try_from(json) {
+        Ok(Bson::Document(d)) => d.get_array("collections").ok().cloned(),
+        _ => None,
+    }
+    .ok_or_else(|| Error::Usage(format!("{}: no collections array", path.display())))?;
+    let colls: Vec<Document> = colls
+        .into_iter()
+        .filter_map(|c| c.as_document().cloned())
+        .collect();
+
+    for c in restore_order(colls) {
+        let name = c
+            .get_str("name")
+            .map_err(|_| Error::Usage(format!("{}: collection without name", path.display())))?;
+        let is_view = c.get_str("type") == Ok("view");
+
+        if ropts.drop {
+            import::drop_collection(db, session, name, opts, ctx)?;
+        }
+
+        let mut cmd = doc! { "create": name };
+        cmd.extend(c.get_document("options").cloned().unwrap_or_default());
+        debug!("Call create: {}", name);
+        match run_command(
+            db,
+            session,
+            with_max_time(with_write_concern(cmd, opts, ctx)?, opts),
+        ) {
+            Ok(_) => {}
+            // NamespaceExists, then restore into the existing collection.
+            Err(e) if e.is_command_code(48) && !is_view => {
+                info!("Collection already exists: {}", name)
+            }
+            Err(e) => return Err(e),
+        }
+
+        if is_view {
+            write_doc(&doc! { "view": name }, opts, ctx)?;
+            continue;
+        }
+
+        let indexes: Vec<Bson> = c
+            .get_array("indexes")
+            .map(|i| &i[..])
+            .unwrap_or(&[])
+            .iter()
+            .filter(|i| i.as_document().and_then(|d| d.get_str("name").ok()) != Some("_id_"))
+            .cloned()
+            .collect();
+        let n_indexes = indexes.len() as i64;
+        if !indexes.is_empty() {
+            debug!("Call createIndexes: {}", name);
+            let cmd = doc! { "createIndexes": name, "indexes": indexes };
+            run_command(
+                db,
+                session,
+                with_max_time(with_write_concern(cmd, opts, ctx)?, opts),
+            )?;
+        }
+
+        let data = ropts.dir.join(data_file(name));
+        let stats = if data.exists() {
+            let iopts = ImportOpts {
+                collection: name.to_string(),
+                format: ImportFormat::Json,
+                ordered: true,
+                upsert_fields: vec![],
+                drop: false,
+                batch_size: INSERT_BATCH,
+            };
+            let file = io::BufReader::new(fs::File::open(&data)?);
+            let docs = DocReader::new(file, InputMode::Json);
+            import::import_docs(db, session, &iopts, opts, ctx, docs)?
+        } else {
+            warn!("No data file: {}", data.display());
+            import::Stats::default()
+        };
+
+        write_doc(
+            &doc! {
+                "collection": name,
+                "nIndexes": n_indexes,
+                "nInserted": stats.inserted,
+            },
+            opts,
+            ctx,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Views are created after the collections, since they may be on any of them, and a view on
+/// another view after that view.
+fn restore_order(colls: Vec<Document>) -> Vec<Document> {
+    let (mut views, mut order): (Vec<_>, Vec<_>) = colls
+        .into_iter()
+        .partition(|c| c.get_str("type") == Ok("view"));
+    while !views.is_empty() {
+        let pending: Vec<String> = views
+            .iter()
+            .filter_map(|v| v.get_str("name").ok().map(String::from))
+            .collect();
+        let (ready, rest): (Vec<_>, Vec<_>) = views.into_iter().partition(|v| {
+            let on = v
+                .get_document("options")
+                .and_then(|o| o.get_str("viewOn"))
+                .unwrap_or_default();
+            !pending.iter().any(|p| p == on)
+        });
+        if ready.is_empty() {
+            // a cycle can't be created, keep whatever is left as it is.
+            order.extend(rest);
+            break;
+        }
+        order.extend(ready);
+        views = rest;
+    }
+    order
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn views_last() {
+        let colls = vec![
+            doc! { "name": "active", "type": "view", "options": { "viewOn": "users" } },
+            doc! { "name": "new", "type": "view", "options": { "viewOn": "recent" } },
+            doc! { "name": "recent", "type": "view", "options": { "viewOn": "active" } },
+            doc! { "name": "users", "type": "collection", "options": {} },
+            doc! { "name": "metrics", "type": "timeseries", "options": {} },
+        ];
+        let names: Vec<_> = restore_order(colls)
+            .iter()
+            .map(|c| c.get_str("name").unwrap().to_string())
+            .collect();
+        assert_eq!(names, vec!["users", "metrics", "active", "recent", "new"]);
+    }
+
+    #[test]
+    fn data_files() {
+        assert_eq!(data_file("users"), "users.jsonl");
+        assert_eq!(data_file("app.events"), "app.events.jsonl");
+        assert_eq!(data_file(".."), "%2E..jsonl");
+        assert_eq!(data_file("../x"), "%2E.%2Fx.jsonl");
+        assert_eq!(data_file("a/b%c"), "a%2Fb%25c.jsonl");
+        assert_ne!(data_file("a/b"), data_file("a%2Fb"));
+    }
+
+    #[test]
+    fn round_trip() {
+        use bson::spec::BinarySubtype;
+        use bson::Binary;
+        use bson::Decimal128;
+
+        let docs = vec![
+            doc! {
+                "_id": bson::oid::ObjectId::parse_str("5f8c6c3e1c9d440000a1b2c3").unwrap(),
+                "int": 1,
+                "long": 1_i64,
+                "big": i64::MAX,
+                "double": 1.0,
+                "decimal": "1.50".parse::<Decimal128>().unwrap(),
+                "at": bson::DateTime::from_millis(1_600_000_000_123),
+                "before1970": bson::DateTime::from_millis(-1),
+                "bin": Binary { subtype: BinarySubtype::Generic, bytes: vec![0, 1, 255] },
+                "uuid": Binary { subtype: BinarySubtype::Uuid, bytes: vec![7; 16] },
+                "nested": { "a": [1_i64, "x", null] },
+            },
+            doc! { "_id": 2 },
+        ];
+        let mut buf = vec![];
+        for doc in &docs {
+            write_line(&mut buf, doc.clone()).unwrap();
+        }
+        let read: Vec<Document> = DocReader::new(&buf[..], InputMode::Json)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, docs);
+    }
+}
@@ -5,6 +5,8 @@ use std::io;
 pub enum Error {
     Parse(String),
     Usage(String),
+    /// Some of the work failed, which has already been reported in detail.
+    Failed(String),
     MongoDb(mongodb::error::Error),
     Bson(bson::ser::Error),
     Json(serde_json::Error),
@@ -12,12 +14,79 @@ pub enum Error {
     Io(io::Error),
 }
 
+/// Process exit codes, so scripts can tell failures apart.
+pub const EXIT_OTHER: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONNECTION: i32 = 3;
+pub const EXIT_AUTH: i32 = 4;
+pub const EXIT_TIMEOUT: i32 = 5;
+
+/// The failures a user can act on, as opposed to everything else the driver reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Connection,
+    Auth,
+    Timeout,
+    Other,
+}
+
+fn classify(e: &mongodb::error::Error) -> Failure {
+    use mongodb::error::ErrorKind;
+    match &*e.kind {
+        ErrorKind::ServerSelection { .. }
+        | ErrorKind::DnsResolve { .. }
+        | ErrorKind::ConnectionPoolCleared { .. }
+        | ErrorKind::InvalidTlsConfig { .. } => Failure::Connection,
+        ErrorKind::Io(io) => match io.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Failure::Timeout,
+            _ => Failure::Connection,
+        },
+        ErrorKind::Authentication { .. } => Failure::Auth,
+        // AuthenticationFailed, Unauthorized and MaxTimeMSExpired
+        ErrorKind::Command(c) if c.code == 18 || c.code == 13 => Failure::Auth,
+        ErrorKind::Command(c) if c.code == 50 => Failure::Timeout,
+        _ => Failure::Other,
+    }
+}
+
+impl Error {
+    /// Whether this is a server command error with the given code, like 26 for
+    /// NamespaceNotFound.
+    pub fn is_command_code(&self, code: i32) -> bool {
+        match self {
+            Error::MongoDb(e) => {
+                matches!(&*e.kind, mongodb::error::ErrorKind::Command(c) if c.code == code)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Parse(_) | Error::Usage(_) => EXIT_USAGE,
+            Error::MongoDb(e) => match classify(e) {
+                Failure::Connection => EXIT_CONNECTION,
+                Failure::Auth => EXIT_AUTH,
+                Failure::Timeout => EXIT_TIMEOUT,
+                Failure::Other => EXIT_OTHER,
+            },
+            _ => EXIT_OTHER,
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Parse(v) => write!(f, "parser: {}", v),
             Error::Usage(v) => write!(f, "{}", v),
-            Error::MongoDb(v) => write!(f, "{}", v),
+            Error::Failed(v) => write!(f, "{}", v),
+            Error::MongoDb(v) => match classify(v) {
+                Failure::Connection => write!(f, "Could not connect to server: {}", v.kind),
+                Failure::Auth => write!(f, "Authentication failed: {}", v.kind),
+                Failure::Timeout => write!(f, "Timed out: {}", v.kind),
+                Failure::Other => write!(f, "{}", v),
+            },
             Error::Bson(v) => write!(f, "bson: {}", v),
             Error::Json(v) => write!(f, "json: {}", v),
             Error::Json5(v) => write!(f, "json5: {}", v),
@@ -69,3 +138,32 @@ impl From<io::Error> for Error {
         Error::Io(v)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bson::doc;
+    use mongodb::error::CommandError;
+    use mongodb::error::ErrorKind;
+
+    fn command_error(code: i32) -> mongodb::error::Error {
+        let e: CommandError =
+            bson::from_document(doc! { "code": code, "codeName": "", "errmsg": "" }).unwrap();
+        ErrorKind::Command(e).into()
+    }
+
+    #[test]
+    fn classify_failures() {
+        assert_eq!(classify(&command_error(50)), Failure::Timeout);
+        assert_eq!(classify(&command_error(18)), Failure::Auth);
+        assert_eq!(classify(&command_error(13)), Failure::Auth);
+        assert_eq!(classify(&command_error(26)), Failure::Other);
+        let timeout = io::Error::new(io::ErrorKind::TimedOut, "timed out");
+        assert_eq!(classify(&timeout.into()), Failure::Timeout);
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(classify(&refused.into()), Failure::Connection);
+
+        assert_eq!(Error::MongoDb(command_error(50)).exit_code(), EXIT_TIMEOUT);
+        assert_eq!(Error::Usage("x".into()).exit_code(), EXIT_USAGE);
+    }
+}
@@ -0,0 +1,543 @@
+use crate::error::Error;
+use crate::input::DocReader;
+use crate::input::InputMode;
+use crate::{run_command, with_max_time, with_write_concern, write_doc, Context, Opts};
+use bson::doc;
+use bson::oid::ObjectId;
+use bson::Bson;
+use bson::Document;
+use mongodb::sync::ClientSession;
+use mongodb::sync::Database;
+use std::convert::TryFrom;
+use std::io;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Documents per insert command are also capped by size, to stay clear of the 16MB
+/// command limit.
+const MAX_BATCH_BYTES: usize = 8 * 1024 * 1024;
+
+#[derive(StructOpt, Debug)]
+pub struct ImportOpts {
+    /// Collection to import into
+    pub collection: String,
+
+    /// Input format, json (JSONL or arrays of documents), csv, tsv or bson
+    #[structopt(long, default_value = "json")]
+    pub format: ImportFormat,
+
+    /// Whether to stop at the first document that fails
+    #[structopt(long, default_value = "true", parse(try_from_str))]
+    pub ordered: bool,
+
+    /// Comma separated fields that identify a document, to replace (or insert) documents
+    /// instead of only inserting
+    #[structopt(long, use_delimiter = true)]
+    pub upsert_fields: Vec<String>,
+
+    /// Drop the collection before importing
+    #[structopt(long)]
+    pub drop: bool,
+
+    /// Documents per insert command
+    #[structopt(long, default_value = "1000")]
+    pub batch_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Json,
+    Csv,
+    Tsv,
+    Bson,
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" | "jsonl" => Ok(ImportFormat::Json),
+            "csv" => Ok(ImportFormat::Csv),
+            "tsv" => Ok(ImportFormat::Tsv),
+            "bson" => Ok(ImportFormat::Bson),
+            _ => Err(format!(
+                "Unknown import format: {} (expected json, csv, tsv or bson)",
+                s
+            )),
+        }
+    }
+}
+
+/// Imports documents from stdin into a collection, batch by batch.
+pub fn handle_import(
+    db: &Database,
+    session: &mut ClientSession,
+    iopts: &ImportOpts,
+    opts: &Opts,
+    ctx: &Context,
+) -> Result<(), Error> {
+    let stdin = io::stdin();
+    let docs: Box<dyn Iterator<Item = Result<Document, Error>>> = match iopts.format {
+        ImportFormat::Json => Box::new(DocReader::new(stdin.lock(), InputMode::Json)),
+        ImportFormat::Bson => Box::new(DocReader::new(stdin.lock(), InputMode::Bson)),
+        ImportFormat::Csv => Box::new(TableReader::new(stdin.lock(), b',')?),
+        ImportFormat::Tsv => Box::new(TableReader::new(stdin.lock(), b'\t')?),
+    };
+
+    let stats = import_docs(db, session, iopts, opts, ctx, docs)?;
+    write_doc(&stats.to_doc(), opts, ctx)?;
+
+    if stats.failed > 0 {
+        return Err(Error::Failed(format!("{} documents failed", stats.failed)));
+    }
+    Ok(())
+}
+
+/// Inserts (or upserts) the documents in batches, after dropping the collection if asked to.
+pub fn import_docs<I>(
+    db: &Database,
+    session: &mut ClientSession,
+    iopts: &ImportOpts,
+    opts: &Opts,
+    ctx: &Context,
+    docs: I,
+) -> Result<Stats, Error>
+where
+    I: Iterator<Item = Result<Document, Error>>,
+{
+    if iopts.batch_size == 0 {
+        return Err(Error::Usage("--batch-size must be at least 1".into()));
+    }
+
+    if iopts.drop {
+        drop_collection(db, session, &iopts.collection, opts, ctx)?;
+    }
+
+    let mut import = Import::new(db, session, iopts, opts, ctx);
+    for doc in docs {
+        let doc = doc.map_err(|e| Error::Usage(format!("Document {}: {}", import.read + 1, e)))?;
+        import.push(doc)?;
+    }
+    import.finish()
+}
+
+/// Drops a collection, which is fine if it doesn't exist.
+pub fn drop_collection(
+    db: &Database,
+    session: &mut ClientSession,
+    collection: &str,
+    opts: &Opts,
+    ctx: &Context,
+) -> Result<(), Error> {
+    info!("Drop collection: {}", collection);
+    let cmd = with_max_time(
+        with_write_concern(doc! { "drop": collection }, opts, ctx)?,
+        opts,
+    );
+    match run_command(db, session, cmd) {
+        Ok(_) => Ok(()),
+        // NamespaceNotFound
+        Err(e) if e.is_command_code(26) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub inserted: i64,
+    pub upserted: i64,
+    /// Existing documents an upsert matched, whether or not it changed them.
+    pub matched: i64,
+    pub modified: i64,
+    pub failed: i64,
+}
+
+impl Stats {
+    pub fn to_doc(&self) -> Document {
+        doc! {
+            "nInserted": self.inserted,
+            "nUpserted": self.upserted,
+            "nMatched": self.matched,
+            "nModified": self.modified,
+            "nFailed": self.failed,
+        }
+    }
+}
+
+/// Collects documents into batches, and inserts (or upserts) each batch when it is full.
+pub struct Import<'a> {
+    db: &'a Database,
+    session: &'a mut ClientSession,
+    iopts: &'a ImportOpts,
+    opts: &'a Opts,
+    ctx: &'a Context,
+    batch: Vec<Document>,
+    batch_bytes: usize,
+    /// Number of documents read so far, to point out failing documents.
+    read: usize,
+    stats: Stats,
+}
+
+impl<'a> Import<'a> {
+    pub fn new(
+        db: &'a Database,
+        session: &'a mut ClientSession,
+        iopts: &'a ImportOpts,
+        opts: &'a Opts,
+        ctx: &'a Context,
+    ) -> Self {
+        Import {
+            db,
+            session,
+            iopts,
+            opts,
+            ctx,
+            batch: vec![],
+            batch_bytes: 0,
+            read: 0,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Inserts the last batch.
+    pub fn finish(mut self) -> Result<Stats, Error> {
+        self.flush()?;
+        let stats = self.stats;
+        info!(
+            "Imported {} documents into {}, {} failed",
+            stats.inserted + stats.upserted + stats.matched,
+            self.iopts.collection,
+            stats.failed
+        );
+        Ok(stats)
+    }
+
+    pub fn push(&mut self, doc: Document) -> Result<(), Error> {
+        let size = bson::to_vec(&doc)?.len();
+        if !self.batch.is_empty() && self.batch_bytes + size > MAX_BATCH_BYTES {
+            self.flush()?;
+        }
+        self.read += 1;
+        self.batch_bytes += size;
+        self.batch.push(doc);
+        if self.batch.len() >= self.iopts.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        self.batch_bytes = 0;
+        // input position of the first document in the batch, 1-based.
+        let first = self.read - batch.len() + 1;
+        let len = batch.len();
+
+        let coll = &self.iopts.collection;
+        let cmd = if self.iopts.upsert_fields.is_empty() {
+            doc! {
+                "insert": coll,
+                "documents": batch,
+                "ordered": self.iopts.ordered,
+            }
+        } else {
+            let mut updates = Vec::with_capacity(batch.len());
+            for doc in batch {
+                let q = upsert_filter(&doc, &self.iopts.upsert_fields)?;
+                updates.push(doc! { "q": q, "u": doc, "upsert": true });
+            }
+            doc! {
+                "update": coll,
+                "updates": updates,
+                "ordered": self.iopts.ordered,
+            }
+        };
+
+        debug!("Import batch of {} documents", len);
+        let cmd = with_max_time(with_write_concern(cmd, self.opts, self.ctx)?, self.opts);
+        let res = run_command(self.db, self.session, cmd)?;
+
+        let n = res.get_i32("n").map(i64::from).unwrap_or(0);
+        if self.iopts.upsert_fields.is_empty() {
+            self.stats.inserted += n;
+        } else {
+            let upserted = res
+                .get_array("upserted")
+                .map(|u| u.len() as i64)
+                .unwrap_or(0);
+            self.stats.upserted += upserted;
+            self.stats.matched += n - upserted;
+            self.stats.modified += res.get_i32("nModified").map(i64::from).unwrap_or(0);
+        }
+
+        let errors = res.get_array("writeErrors").map(|e| &e[..]).unwrap_or(&[]);
+        for err in errors.iter().filter_map(|e| e.as_document()) {
+            let index = err.get_i32("index").unwrap_or(0) as usize;
+            warn!(
+                "Document {}: {}",
+                first + index,
+                err.get_str("errmsg").unwrap_or("failed")
+            );
+        }
+        self.stats.failed += errors.len() as i64;
+
+        info!(
+            "Imported {} documents",
+            self.stats.inserted + self.stats.upserted + self.stats.matched
+        );
+
+        if self.iopts.ordered && !errors.is_empty() {
+            return Err(Error::Failed(format!(
+                "Import stopped at document {} ({} documents not attempted)",
+                first
+                    + errors[0]
+                        .as_document()
+                        .and_then(|e| e.get_i32("index").ok())
+                        .unwrap_or(0) as usize,
+                len - n as usize - 1,
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn upsert_filter(doc: &Document, fields: &[String]) -> Result<Document, Error> {
+    let mut q = Document::new();
+    for f in fields {
+        let v = crate::output::lookup(doc, f)
+            .ok_or_else(|| Error::Usage(format!("Document without upsert field {}: {}", f, doc)))?;
+        q.insert(f.clone(), v);
+    }
+    Ok(q)
+}
+
+/// The type of a CSV column, from a header annotation like `age:int`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+    String,
+    Int,
+    Long,
+    Double,
+    Decimal,
+    Bool,
+    Date,
+    ObjectId,
+    Json,
+}
+
+impl FromStr for ColumnType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "string" => Ok(ColumnType::String),
+            "int" => Ok(ColumnType::Int),
+            "long" => Ok(ColumnType::Long),
+            "double" => Ok(ColumnType::Double),
+            "decimal" => Ok(ColumnType::Decimal),
+            "bool" => Ok(ColumnType::Bool),
+            "date" => Ok(ColumnType::Date),
+            "oid" => Ok(ColumnType::ObjectId),
+            "json" => Ok(ColumnType::Json),
+            _ => Err(format!("Unknown column type: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Column {
+    path: String,
+    typ: ColumnType,
+}
+
+fn parse_header(header: &csv::StringRecord) -> Result<Vec<Column>, Error> {
+    header
+        .iter()
+        .map(|h| {
+            let (path, typ) = match h.rsplit_once(':') {
+                Some((p, t)) => (p, t.parse()?),
+                None => (h, ColumnType::String),
+            };
+            Ok(Column {
+                path: path.trim().to_string(),
+                typ,
+            })
+        })
+        .collect()
+}
+
+fn parse_cell(s: &str, typ: ColumnType) -> Result<Bson, String> {
+    let v = match typ {
+        ColumnType::String => Bson::String(s.to_string()),
+        ColumnType::Int => Bson::Int32(s.trim().parse().map_err(|e| format!("{}: {}", s, e))?),
+        ColumnType::Long => Bson::Int64(s.trim().parse().map_err(|e| format!("{}: {}", s, e))?),
+        ColumnType::Double => Bson::Double(s.trim().parse().map_err(|e| format!("{}: {}", s, e))?),
+        ColumnType::Decimal => {
+            Bson::Decimal128(s.trim().parse().map_err(|e| format!("{}: {:?}", s, e))?)
+        }
+        ColumnType::Bool => match s.trim() {
+            "true" | "1" => Bson::Boolean(true),
+            "false" | "0" => Bson::Boolean(false),
+            _ => return Err(format!("Not a bool: {}", s)),
+        },
+        ColumnType::Date => {
+            let s = s.trim();
+            match s.parse::<i64>() {
+                Ok(millis) => Bson::DateTime(bson::DateTime::from_millis(millis)),
+                Err(_) => Bson::DateTime(
+                    bson::DateTime::parse_rfc3339_str(s).map_err(|e| format!("{}: {}", s, e))?,
+                ),
+            }
+        }
+        ColumnType::ObjectId => {
+            Bson::ObjectId(ObjectId::parse_str(s.trim()).map_err(|e| format!("{}: {}", s, e))?)
+        }
+        ColumnType::Json => {
+            let json: serde_json::Value = serde_json::from_str(s).map_err(|e| e.to_string())?;
+            Bson::try_from(json).map_err(|e| e.to_string())?
+        }
+    };
+    Ok(v)
+}
+
+/// Sets a dotted path, creating the nested documents on the way.
+fn set_path(doc: &mut Document, path: &str, v: Bson) {
+    match path.split_once('.') {
+        None => {
+            doc.insert(path, v);
+        }
+        Some((head, rest)) => {
+            if !matches!(doc.get(head), Some(Bson::Document(_))) {
+                doc.insert(head, Document::new());
+            }
+            if let Some(Bson::Document(d)) = doc.get_mut(head) {
+                set_path(d, rest, v);
+            }
+        }
+    }
+}
+
+/// Reads CSV or TSV rows as documents, using the header row for field names and types.
+struct TableReader<R: io::Read> {
+    records: csv::StringRecordsIntoIter<R>,
+    columns: Vec<Column>,
+    tsv: bool,
+}
+
+impl<R: io::Read> TableReader<R> {
+    fn new(read: R, delimiter: u8) -> Result<Self, Error> {
+        let tsv = delimiter == b'\t';
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            // TSV escapes instead of quoting, like --output tsv.
+            .quoting(!tsv)
+            .from_reader(read);
+        let columns = parse_header(reader.headers().map_err(csv_error)?)?;
+        Ok(TableReader {
+            records: reader.into_records(),
+            columns,
+            tsv,
+        })
+    }
+
+    fn to_doc(&self, record: &csv::StringRecord) -> Result<Document, Error> {
+        let mut doc = Document::new();
+        for (col, cell) in self.columns.iter().zip(record.iter()) {
+            let cell = if self.tsv {
+                tsv_unescape(cell)
+            } else {
+                cell.to_string()
+            };
+            // blank cells are left out, except for strings.
+            if cell.is_empty() && col.typ != ColumnType::String {
+                continue;
+            }
+            let v = parse_cell(&cell, col.typ)
+                .map_err(|e| Error::Usage(format!("Column {}: {}", col.path, e)))?;
+            set_path(&mut doc, &col.path, v);
+        }
+        Ok(doc)
+    }
+}
+
+impl<R: io::Read> Iterator for TableReader<R> {
+    type Item = Result<Document, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.records.next()?;
+        Some(record.map_err(csv_error).and_then(|r| self.to_doc(&r)))
+    }
+}
+
+fn csv_error(e: csv::Error) -> Error {
+    Error::Usage(format!("csv: {}", e))
+}
+
+fn tsv_unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(input: &str, delimiter: u8) -> Result<Vec<Document>, Error> {
+        TableReader::new(input.as_bytes(), delimiter)?.collect()
+    }
+
+    #[test]
+    fn csv_typed_columns() {
+        let docs = read(
+            "name,age:int,address.city,active:bool,joined:date,score:double\n\
+             \"Martin, Jr\",42,Stockholm,true,2020-09-13T12:26:40Z,\n",
+            b',',
+        )
+        .unwrap();
+        assert_eq!(
+            docs,
+            vec![doc! {
+                "name": "Martin, Jr",
+                "age": 42,
+                "address": { "city": "Stockholm" },
+                "active": true,
+                "joined": bson::DateTime::from_millis(1_600_000_000_000),
+            }]
+        );
+
+        assert!(read("age:int\nabc\n", b',').is_err());
+        assert!(read("age:nope\n1\n", b',').is_err());
+    }
+
+    #[test]
+    fn tsv_escapes() {
+        let docs = read("a\tb:long\nx\\ty\t9\n", b'\t').unwrap();
+        assert_eq!(docs, vec![doc! { "a": "x\ty", "b": 9_i64 }]);
+    }
+
+    #[test]
+    fn upsert_filter_fields() {
+        let doc = doc! { "_id": 1, "a": { "b": 2 }, "c": 3 };
+        let q = upsert_filter(&doc, &["_id".into(), "a.b".into()]).unwrap();
+        assert_eq!(q, doc! { "_id": 1, "a.b": 2 });
+        assert!(upsert_filter(&doc, &["nope".into()]).is_err());
+    }
+}
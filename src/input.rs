@@ -0,0 +1,241 @@
+use crate::error::Error;
+use bson::Bson;
+use bson::Document;
+use serde::Deserialize;
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::io;
+use std::str::FromStr;
+
+/// The format of documents read from stdin by `insert(-)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputMode {
+    /// A stream of JSON documents (or arrays of documents), which may use extended JSON
+    /// like `{"$oid": "..."}` for the BSON types.
+    #[default]
+    Json,
+    /// Concatenated BSON documents, as written by `--output bson` or mongodump.
+    Bson,
+}
+
+impl FromStr for InputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(InputMode::Json),
+            "bson" => Ok(InputMode::Bson),
+            _ => Err(format!("Unknown input mode: {} (expected json or bson)", s)),
+        }
+    }
+}
+
+/// Reads documents one by one from a stream.
+pub struct DocReader<'a> {
+    inner: Inner<'a>,
+}
+
+enum Inner<'a> {
+    Json {
+        read: Box<dyn io::BufRead + 'a>,
+        array: ArrayState,
+    },
+    Bson(Box<dyn io::Read + 'a>),
+}
+
+/// Where the JSON reader is relative to a top-level array, like from mongoexport
+/// --jsonArray, whose elements are read one at a time rather than the whole array at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArrayState {
+    Outside,
+    /// After the `[`, where the array might be empty.
+    Start,
+    /// After a `,`, where an element must follow.
+    Element,
+    /// After an element, where a `,` or the `]` must follow.
+    Next,
+}
+
+impl<'a> DocReader<'a> {
+    pub fn new<R: io::Read + 'a>(read: R, mode: InputMode) -> Self {
+        let inner = match mode {
+            InputMode::Json => Inner::Json {
+                read: Box::new(io::BufReader::new(read)),
+                array: ArrayState::Outside,
+            },
+            InputMode::Bson => Inner::Bson(Box::new(io::BufReader::new(read))),
+        };
+        DocReader { inner }
+    }
+
+    fn next_doc(&mut self) -> Result<Option<Document>, Error> {
+        match &mut self.inner {
+            Inner::Json { read, array } => loop {
+                let b = skip_json_white(read)?;
+                match (*array, b) {
+                    (ArrayState::Outside, None) => return Ok(None),
+                    (_, None) => return Err(Error::Usage("Unterminated JSON array".into())),
+                    (ArrayState::Outside, Some(b'[')) => {
+                        read.consume(1);
+                        *array = ArrayState::Start;
+                    }
+                    (ArrayState::Start, Some(b']')) | (ArrayState::Next, Some(b']')) => {
+                        read.consume(1);
+                        *array = ArrayState::Outside;
+                    }
+                    (ArrayState::Next, Some(b',')) => {
+                        read.consume(1);
+                        *array = ArrayState::Element;
+                    }
+                    (ArrayState::Next, Some(b)) => {
+                        return Err(Error::Usage(format!(
+                            "Expected , or ] after document in JSON array, got: {}",
+                            b as char
+                        )))
+                    }
+                    (state, Some(_)) => {
+                        // a document ends with its }, so the deserializer reads no further.
+                        let mut de = serde_json::Deserializer::from_reader(&mut *read);
+                        let v = Value::deserialize(&mut de)?;
+                        if state != ArrayState::Outside {
+                            *array = ArrayState::Next;
+                        }
+                        return json_to_doc(v).map(Some);
+                    }
+                }
+            },
+            Inner::Bson(read) => read_bson_doc(read),
+        }
+    }
+}
+
+/// Skips JSON whitespace and peeks at the next byte, if any.
+fn skip_json_white<R: io::BufRead + ?Sized>(read: &mut R) -> Result<Option<u8>, Error> {
+    loop {
+        let buf = read.fill_buf()?;
+        match buf
+            .iter()
+            .position(|b| !matches!(b, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            Some(i) => {
+                let b = buf[i];
+                read.consume(i);
+                return Ok(Some(b));
+            }
+            None if buf.is_empty() => return Ok(None),
+            None => {
+                let n = buf.len();
+                read.consume(n);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for DocReader<'a> {
+    type Item = Result<Document, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_doc().transpose()
+    }
+}
+
+fn json_to_doc(v: Value) -> Result<Document, Error> {
+    match Bson::try_from(v) {
+        Ok(Bson::Document(doc)) => Ok(doc),
+        Ok(v) => Err(Error::Usage(format!("Expected a document, got: {}", v))),
+        Err(e) => Err(Error::Usage(format!("Invalid extended JSON: {}", e))),
+    }
+}
+
+/// Reads one length prefixed BSON document, or None at a clean end of stream.
+pub fn read_bson_doc<R: io::Read + ?Sized>(read: &mut R) -> Result<Option<Document>, Error> {
+    let mut len = [0_u8; 4];
+    let mut got = 0;
+    while got < len.len() {
+        match read.read(&mut len[got..])? {
+            0 if got == 0 => return Ok(None),
+            0 => return Err(Error::Usage("Truncated BSON document".into())),
+            n => got += n,
+        }
+    }
+
+    let size = i32::from_le_bytes(len);
+    if size < 5 {
+        return Err(Error::Usage(format!(
+            "Invalid BSON document length: {}",
+            size
+        )));
+    }
+
+    let mut buf = vec![0_u8; size as usize];
+    buf[..4].copy_from_slice(&len);
+    read.read_exact(&mut buf[4..]).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Usage("Truncated BSON document".into()),
+        _ => Error::Io(e),
+    })?;
+
+    let doc = Document::from_reader(&buf[..])
+        .map_err(|e| Error::Usage(format!("Invalid BSON document: {}", e)))?;
+    Ok(Some(doc))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bson::doc;
+
+    #[test]
+    fn read_bson() {
+        let mut buf = vec![];
+        doc! { "a": 1_i64 }.to_writer(&mut buf).unwrap();
+        doc! { "b": "x" }.to_writer(&mut buf).unwrap();
+        let docs: Vec<Document> = DocReader::new(&buf[..], InputMode::Bson)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(docs, vec![doc! { "a": 1_i64 }, doc! { "b": "x" }]);
+
+        let truncated = &buf[..buf.len() - 2];
+        let res: Result<Vec<Document>, Error> =
+            DocReader::new(truncated, InputMode::Bson).collect();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn read_json() {
+        let input = r#"{"a": {"$numberLong": "1"}} [{"b": 2}, {"c": 3}]
+            {"d": {"$date": "2020-09-13T12:26:40Z"}}"#;
+        let docs: Vec<Document> = DocReader::new(input.as_bytes(), InputMode::Json)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(docs.len(), 4);
+        assert_eq!(docs[0], doc! { "a": 1_i64 });
+        assert!(docs[3].get_datetime("d").is_ok());
+
+        let res: Result<Vec<Document>, Error> =
+            DocReader::new("1".as_bytes(), InputMode::Json).collect();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn read_json_array() {
+        let input = r#"[] [ {"a": 1} ,{"b": [2]}]{"c": 3}"#;
+        let docs: Vec<Document> = DocReader::new(input.as_bytes(), InputMode::Json)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            docs,
+            vec![doc! { "a": 1 }, doc! { "b": [2] }, doc! { "c": 3 }]
+        );
+
+        // the elements are read one at a time, so the first comes before the broken rest.
+        let mut reader = DocReader::new(r#"[{"a": 1} {"b": 2}]"#.as_bytes(), InputMode::Json);
+        assert_eq!(reader.next().unwrap().unwrap(), doc! { "a": 1 });
+        assert!(reader.next().unwrap().is_err());
+
+        for input in &[r#"[{"a": 1},"#, r#"[{"a": 1},]"#, "[1]"] {
+            let res: Result<Vec<Document>, Error> =
+                DocReader::new(input.as_bytes(), InputMode::Json).collect();
+            assert!(res.is_err(), "{}", input);
+        }
+    }
+}
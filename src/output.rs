@@ -0,0 +1,481 @@
+use bson::spec::BinarySubtype;
+use bson::Bson;
+use bson::Document;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use serde_json::Value;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// How BSON values are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Extended JSON v2 relaxed, i.e. `{"$oid": "..."}`, `{"$date": "2020-01-01T00:00:00Z"}`
+    /// and plain numbers.
+    #[default]
+    EjsonRelaxed,
+    /// Extended JSON v2 canonical, which keeps every type, like `{"$numberLong": "1"}`.
+    EjsonCanonical,
+    /// mongo shell style, `ObjectId("...")`, `ISODate("...")`. Not JSON.
+    Shell,
+    /// Natural JSON, where ObjectId is a hex string and dates are ISO strings.
+    Plain,
+    /// Comma separated values with a header row, one row per document.
+    Csv,
+    /// Tab separated values with a header row, one row per document.
+    Tsv,
+    /// Concatenated BSON documents, like mongodump and bsondump read.
+    Bson,
+}
+
+impl OutputMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputMode::EjsonRelaxed => "ejson-relaxed",
+            OutputMode::EjsonCanonical => "ejson-canonical",
+            OutputMode::Shell => "shell",
+            OutputMode::Plain => "plain",
+            OutputMode::Csv => "csv",
+            OutputMode::Tsv => "tsv",
+            OutputMode::Bson => "bson",
+        }
+    }
+
+    pub fn is_table(&self) -> bool {
+        matches!(self, OutputMode::Csv | OutputMode::Tsv)
+    }
+}
+
+impl Serialize for OutputMode {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for OutputMode {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ejson-relaxed" => Ok(OutputMode::EjsonRelaxed),
+            "ejson-canonical" => Ok(OutputMode::EjsonCanonical),
+            "shell" => Ok(OutputMode::Shell),
+            "plain" => Ok(OutputMode::Plain),
+            "csv" => Ok(OutputMode::Csv),
+            "tsv" => Ok(OutputMode::Tsv),
+            "bson" => Ok(OutputMode::Bson),
+            _ => Err(format!(
+                "Unknown output mode: {} (expected ejson-relaxed, ejson-canonical, shell, plain, \
+                 csv, tsv or bson)",
+                s
+            )),
+        }
+    }
+}
+
+/// Converts to JSON for all modes except `Shell`, which isn't JSON.
+pub fn to_json(b: &Bson, mode: OutputMode) -> Value {
+    match mode {
+        OutputMode::EjsonRelaxed | OutputMode::Shell => b.clone().into_relaxed_extjson(),
+        OutputMode::EjsonCanonical | OutputMode::Bson => b.clone().into_canonical_extjson(),
+        OutputMode::Plain | OutputMode::Csv | OutputMode::Tsv => to_plain(b),
+    }
+}
+
+/// Looks up a dotted path like `address.city` or `tags.0`. Going through an array of
+/// documents, like `items.sku`, gives an array of the values found in each element.
+pub fn lookup(doc: &Document, path: &str) -> Option<Bson> {
+    let (head, rest) = match path.split_once('.') {
+        Some((h, r)) => (h, Some(r)),
+        None => (path, None),
+    };
+    let v = doc.get(head)?;
+    match rest {
+        None => Some(v.clone()),
+        Some(rest) => lookup_in(v, rest),
+    }
+}
+
+fn lookup_in(v: &Bson, path: &str) -> Option<Bson> {
+    match v {
+        Bson::Document(d) => lookup(d, path),
+        Bson::Array(a) => {
+            let (head, rest) = match path.split_once('.') {
+                Some((h, r)) => (h, Some(r)),
+                None => (path, None),
+            };
+            if let Ok(idx) = head.parse::<usize>() {
+                let v = a.get(idx)?;
+                return match rest {
+                    None => Some(v.clone()),
+                    Some(rest) => lookup_in(v, rest),
+                };
+            }
+            let found: Vec<Bson> = a.iter().filter_map(|v| lookup_in(v, path)).collect();
+            Some(Bson::Array(found))
+        }
+        _ => None,
+    }
+}
+
+/// A table cell. Arrays of plain values are joined with `;`, while documents and
+/// arrays holding documents or arrays are JSON encoded.
+pub fn cell(b: &Bson) -> String {
+    match b {
+        Bson::Null | Bson::Undefined => String::new(),
+        Bson::Array(a) if a.iter().all(|v| !is_nested(v)) => {
+            a.iter().map(cell).collect::<Vec<_>>().join(";")
+        }
+        Bson::Array(_) | Bson::Document(_) => to_plain(b).to_string(),
+        _ => match to_plain(b) {
+            Value::String(s) => s,
+            v => v.to_string(),
+        },
+    }
+}
+
+fn is_nested(b: &Bson) -> bool {
+    matches!(b, Bson::Array(_) | Bson::Document(_))
+}
+
+/// The columns of csv/tsv output, from --fields or else the first document of a statement.
+/// The header row is written before the first row of every statement.
+#[derive(Debug, Default)]
+pub struct Table {
+    fields: Option<Vec<String>>,
+}
+
+impl Table {
+    /// Starts a new statement, which gets its own header.
+    pub fn reset(&mut self) {
+        self.fields = None;
+    }
+
+    /// The lines for a value, preceded by the header if it's the first document.
+    pub fn rows(&mut self, b: &Bson, fields: &[String], mode: OutputMode) -> Vec<String> {
+        match b {
+            Bson::Document(doc) => {
+                let mut rows = vec![];
+                let fields = match &self.fields {
+                    Some(f) => f,
+                    None => {
+                        let f = if fields.is_empty() {
+                            doc.keys().cloned().collect()
+                        } else {
+                            fields.to_vec()
+                        };
+                        rows.push(table_row(&f, mode));
+                        self.fields.get_or_insert(f)
+                    }
+                };
+                let cells: Vec<String> = fields
+                    .iter()
+                    .map(|f| lookup(doc, f).map(|v| cell(&v)).unwrap_or_default())
+                    .collect();
+                rows.push(table_row(&cells, mode));
+                rows
+            }
+            // such as distinct, one row per value.
+            Bson::Array(values) => values.iter().map(|v| table_row(&[cell(v)], mode)).collect(),
+            v => vec![table_row(&[cell(v)], mode)],
+        }
+    }
+}
+
+/// One row of cells as a CSV or TSV line, without line ending. TSV escapes instead of
+/// quoting, like `import --format tsv` reads it.
+pub fn table_row(cells: &[String], mode: OutputMode) -> String {
+    let tsv = mode == OutputMode::Tsv;
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(if tsv { b'\t' } else { b',' })
+        .quote_style(if tsv {
+            csv::QuoteStyle::Never
+        } else {
+            csv::QuoteStyle::Necessary
+        })
+        .from_writer(vec![]);
+    let res = if tsv {
+        writer.write_record(cells.iter().map(|c| tsv_escape(c)))
+    } else {
+        writer.write_record(cells)
+    };
+    // writing to a Vec doesn't fail.
+    res.expect("csv to memory");
+    let bytes = writer.into_inner().expect("csv to memory");
+    let line = String::from_utf8(bytes).expect("csv of strings");
+    line.trim_end_matches(['\r', '\n']).to_string()
+}
+
+fn tsv_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn to_plain(b: &Bson) -> Value {
+    match b {
+        Bson::Document(d) => {
+            Value::Object(d.iter().map(|(k, v)| (k.clone(), to_plain(v))).collect())
+        }
+        Bson::Array(a) => Value::Array(a.iter().map(to_plain).collect()),
+        Bson::ObjectId(id) => Value::String(id.to_hex()),
+        Bson::DateTime(d) => match d.try_to_rfc3339_string() {
+            Ok(s) => Value::String(s),
+            Err(_) => Value::from(d.timestamp_millis()),
+        },
+        Bson::Double(f) if !f.is_finite() => Value::String(f.to_string()),
+        Bson::Decimal128(d) => Value::String(d.to_string()),
+        Bson::Binary(bin) => Value::String(base64(b).unwrap_or_else(|| format!("{:?}", bin.bytes))),
+        Bson::Timestamp(ts) => serde_json::json!({ "t": ts.time, "i": ts.increment }),
+        Bson::RegularExpression(re) => Value::String(format!("/{}/{}", re.pattern, re.options)),
+        Bson::JavaScriptCode(code) => Value::String(code.clone()),
+        Bson::JavaScriptCodeWithScope(c) => Value::String(c.code.clone()),
+        Bson::Symbol(s) => Value::String(s.clone()),
+        Bson::Undefined => Value::Null,
+        Bson::MinKey => Value::String("MinKey".into()),
+        Bson::MaxKey => Value::String("MaxKey".into()),
+        _ => b.clone().into_relaxed_extjson(),
+    }
+}
+
+/// The base64 of a binary, from the extended JSON since bson doesn't expose its encoder.
+fn base64(b: &Bson) -> Option<String> {
+    match b.clone().into_relaxed_extjson() {
+        Value::Object(mut m) => match m.remove("$binary") {
+            Some(Value::Object(mut bin)) => match bin.remove("base64") {
+                Some(Value::String(s)) => Some(s),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Formats in mongo shell style, pretty printed unless `compact`.
+pub fn to_shell(b: &Bson, compact: bool) -> String {
+    let mut out = String::new();
+    write_shell(&mut out, b, compact, 0);
+    out
+}
+
+const INDENT: &str = "  ";
+
+fn newline(out: &mut String, compact: bool, level: usize) {
+    if !compact {
+        out.push('\n');
+        for _ in 0..level {
+            out.push_str(INDENT);
+        }
+    }
+}
+
+fn write_shell_doc(out: &mut String, doc: &Document, compact: bool, level: usize) {
+    if doc.is_empty() {
+        out.push_str("{}");
+        return;
+    }
+    out.push('{');
+    for (i, (k, v)) in doc.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        newline(out, compact, level + 1);
+        out.push_str(&json_string(k));
+        out.push_str(if compact { ":" } else { ": " });
+        write_shell(out, v, compact, level + 1);
+    }
+    newline(out, compact, level);
+    out.push('}');
+}
+
+fn write_shell(out: &mut String, b: &Bson, compact: bool, level: usize) {
+    match b {
+        Bson::Document(d) => write_shell_doc(out, d, compact, level),
+        Bson::Array(a) => {
+            if a.is_empty() {
+                out.push_str("[]");
+                return;
+            }
+            out.push('[');
+            for (i, v) in a.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, compact, level + 1);
+                write_shell(out, v, compact, level + 1);
+            }
+            newline(out, compact, level);
+            out.push(']');
+        }
+        Bson::String(s) => out.push_str(&json_string(s)),
+        Bson::Double(f) if f.is_nan() => out.push_str("NaN"),
+        Bson::Double(f) if f.is_infinite() => {
+            out.push_str(if *f > 0.0 { "Infinity" } else { "-Infinity" })
+        }
+        Bson::Double(f) => write!(out, "{}", f).unwrap(),
+        Bson::Int32(i) => write!(out, "{}", i).unwrap(),
+        Bson::Int64(i) => write!(out, "NumberLong({})", i).unwrap(),
+        Bson::Decimal128(d) => write!(out, "NumberDecimal(\"{}\")", d).unwrap(),
+        Bson::Boolean(v) => write!(out, "{}", v).unwrap(),
+        Bson::Null => out.push_str("null"),
+        Bson::Undefined => out.push_str("undefined"),
+        Bson::ObjectId(id) => write!(out, "ObjectId(\"{}\")", id.to_hex()).unwrap(),
+        Bson::DateTime(d) => match d.try_to_rfc3339_string() {
+            Ok(s) => write!(out, "ISODate(\"{}\")", s).unwrap(),
+            Err(_) => write!(out, "new Date({})", d.timestamp_millis()).unwrap(),
+        },
+        Bson::Timestamp(ts) => write!(out, "Timestamp({}, {})", ts.time, ts.increment).unwrap(),
+        Bson::Binary(bin) if bin.subtype == BinarySubtype::Uuid && bin.bytes.len() == 16 => {
+            let h: String = bin.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            write!(
+                out,
+                "UUID(\"{}-{}-{}-{}-{}\")",
+                &h[0..8],
+                &h[8..12],
+                &h[12..16],
+                &h[16..20],
+                &h[20..32]
+            )
+            .unwrap()
+        }
+        Bson::Binary(bin) => write!(
+            out,
+            "BinData({}, \"{}\")",
+            u8::from(bin.subtype),
+            base64(b).unwrap_or_default()
+        )
+        .unwrap(),
+        Bson::RegularExpression(re) => write!(out, "/{}/{}", re.pattern, re.options).unwrap(),
+        Bson::MinKey => out.push_str("MinKey()"),
+        Bson::MaxKey => out.push_str("MaxKey()"),
+        // the rest are deprecated types, which have no better form than extended JSON.
+        _ => out.push_str(&b.clone().into_relaxed_extjson().to_string()),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    Value::String(s.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bson::doc;
+    use bson::oid::ObjectId;
+
+    fn sample() -> Bson {
+        let id = ObjectId::parse_str("5f8c6c3e1c9d440000a1b2c3").unwrap();
+        Bson::Document(doc! {
+            "_id": id,
+            "at": bson::DateTime::from_millis(1_600_000_000_000),
+            "n": 42_i64,
+            "tags": ["a"],
+        })
+    }
+
+    #[test]
+    fn ejson() {
+        let relaxed = to_json(&sample(), OutputMode::EjsonRelaxed).to_string();
+        assert_eq!(
+            relaxed,
+            r#"{"_id":{"$oid":"5f8c6c3e1c9d440000a1b2c3"},"at":{"$date":"2020-09-13T12:26:40Z"},"n":42,"tags":["a"]}"#
+        );
+        let canonical = to_json(&sample(), OutputMode::EjsonCanonical).to_string();
+        assert!(canonical.contains(r#""n":{"$numberLong":"42"}"#));
+        assert!(canonical.contains(r#""at":{"$date":{"$numberLong":"1600000000000"}}"#));
+    }
+
+    #[test]
+    fn plain() {
+        let plain = to_json(&sample(), OutputMode::Plain).to_string();
+        assert_eq!(
+            plain,
+            r#"{"_id":"5f8c6c3e1c9d440000a1b2c3","at":"2020-09-13T12:26:40Z","n":42,"tags":["a"]}"#
+        );
+    }
+
+    #[test]
+    fn table() {
+        let doc = doc! {
+            "name": "Martin, Jr",
+            "address": { "city": "Stockholm" },
+            "tags": ["a", "b"],
+            "items": [{ "sku": "x1" }, { "sku": "x2" }],
+            "nested": [{ "a": 1 }],
+        };
+        let fields = [
+            "name",
+            "address.city",
+            "tags",
+            "tags.1",
+            "items.sku",
+            "nested",
+            "nope",
+        ];
+        let cells: Vec<String> = fields
+            .iter()
+            .map(|f| lookup(&doc, f).map(|v| cell(&v)).unwrap_or_default())
+            .collect();
+        assert_eq!(
+            table_row(&cells, OutputMode::Csv),
+            r#""Martin, Jr",Stockholm,a;b,b,x1;x2,"[{""a"":1}]","#
+        );
+        assert_eq!(
+            table_row(&["a\tb".into(), "c".into()], OutputMode::Tsv),
+            "a\\tb\tc"
+        );
+    }
+
+    #[test]
+    fn table_header_per_statement() {
+        let mut table = Table::default();
+        let rows = table.rows(
+            &Bson::Document(doc! { "a": 1, "b": "x" }),
+            &[],
+            OutputMode::Csv,
+        );
+        assert_eq!(rows, vec!["a,b", "1,x"]);
+        let rows = table.rows(
+            &Bson::Document(doc! { "a": 2, "c": 3 }),
+            &[],
+            OutputMode::Csv,
+        );
+        assert_eq!(rows, vec!["2,"]);
+
+        // the next statement picks its own columns.
+        table.reset();
+        let rows = table.rows(&Bson::Document(doc! { "n": "a\"b" }), &[], OutputMode::Csv);
+        assert_eq!(rows, vec!["n", r#""a""b""#]);
+        let fields = ["c".to_string()];
+        table.reset();
+        let rows = table.rows(
+            &Bson::Document(doc! { "a": 2, "c": 3 }),
+            &fields,
+            OutputMode::Tsv,
+        );
+        assert_eq!(rows, vec!["c", "3"]);
+    }
+
+    #[test]
+    fn shell() {
+        assert_eq!(
+            to_shell(&sample(), true),
+            r#"{"_id":ObjectId("5f8c6c3e1c9d440000a1b2c3"),"at":ISODate("2020-09-13T12:26:40Z"),"n":NumberLong(42),"tags":["a"]}"#
+        );
+        assert_eq!(
+            to_shell(&Bson::Document(doc! { "a": { "b": [] } }), false),
+            "{\n  \"a\": {\n    \"b\": []\n  }\n}"
+        );
+    }
+}
@@ -1,14 +1,73 @@
 #![allow(clippy::needless_lifetimes)]
 
 use crate::token::{TokenKind, Tokens};
+use bson::doc;
+use bson::Bson;
+use bson::Document;
+use mongodb::options::{
+    Acknowledgment, ReadConcern, ReadPreference, ReadPreferenceOptions, WriteConcern,
+};
 use serde::Deserialize;
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug)]
-pub struct Expr {
-    pub collection: String,
-    pub oper: Oper,
+pub enum Expr {
+    Collection { collection: String, oper: Oper },
+    Database { oper: DbOper },
+    ReplicaSet { oper: RsOper },
+    Sharding { oper: ShOper },
+}
+
+/// The read-only replica set helpers under `rs.`.
+#[derive(Debug)]
+pub enum RsOper {
+    Status,
+    Conf,
+    PrintReplicationInfo,
+    PrintSecondaryReplicationInfo,
+}
+
+/// The read-only sharding helpers under `sh.`.
+#[derive(Debug)]
+pub enum ShOper {
+    Status,
+}
+
+/// Operations on the database itself rather than a collection.
+#[derive(Debug)]
+pub enum DbOper {
+    ShowDbs,
+    ShowCollections,
+    GetCollectionNames,
+    GetCollectionInfos {
+        doc: Option<String>,
+    },
+    Stats,
+    CreateCollection {
+        name: String,
+        copts: Option<String>,
+    },
+    CreateView {
+        name: String,
+        source: String,
+        pipeline: String,
+    },
+    RunCommand {
+        cmd: Document,
+        admin: bool,
+    },
+    CurrentOp {
+        doc: Option<String>,
+    },
+    KillOp {
+        op: Bson,
+    },
+    StartTransaction,
+    CommitTransaction,
+    AbortTransaction,
 }
 
 #[derive(Debug)]
@@ -32,24 +91,113 @@ pub enum Oper {
     },
     Insert {
         doc: String,
+        iopts: InsertOpts,
     },
+    /// `insert(-)` reads the documents from stdin.
+    InsertInput {
+        iopts: InsertOpts,
+    },
+    /// `insert([...])`, where the array is left in the token stream to be read element by
+    /// element with `next_array_doc` and `parse_insert_end`, so it is never held in memory
+    /// all at once.
+    InsertStream,
     Remove {
         doc: String,
+        ropts: RemoveOpts,
+    },
+    CreateIndex {
+        keys: String,
+        iopts: Option<String>,
+    },
+    CreateIndexes {
+        keys: String,
+        iopts: Option<String>,
+    },
+    GetIndexes,
+    DropIndex {
+        index: IndexRef,
+    },
+    HideIndex {
+        index: IndexRef,
+        hidden: bool,
     },
+    IndexStats,
+    Stats,
+    Drop,
+    RenameCollection {
+        to: String,
+        drop_target: bool,
+    },
+    SetValidator {
+        doc: String,
+        vopts: Option<String>,
+    },
+    Checksum {
+        doc: Option<String>,
+        copts: ChecksumOpts,
+    },
+    Aggregate {
+        pipeline: String,
+        aopts: AggregateOpts,
+    },
+}
+
+/// An index pointed out either by name or by its key pattern.
+#[derive(Debug)]
+pub enum IndexRef {
+    Name(String),
+    Keys(String),
 }
 
 #[derive(Debug, Default)]
 pub struct CursorOpts {
     pub batch_size: Option<u32>,
     pub limit: Option<i64>,
-    pub skip: Option<i64>,
+    pub skip: Option<u64>,
     pub sort: Option<String>,
+    pub read_pref: Option<ReadPreference>,
 }
 
 #[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct UpdateOpts {
     pub multi: Option<bool>,
     pub upsert: Option<bool>,
+    #[serde(rename = "writeConcern")]
+    pub write_concern: Option<WriteConcern>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InsertOpts {
+    pub ordered: Option<bool>,
+    #[serde(rename = "writeConcern")]
+    pub write_concern: Option<WriteConcern>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChecksumOpts {
+    /// Only hash these fields, instead of whole documents.
+    pub fields: Option<Vec<String>>,
+    /// Use the server's dbHash of the whole collection.
+    pub server: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AggregateOpts {
+    #[serde(rename = "allowDiskUse")]
+    pub allow_disk_use: Option<bool>,
+    #[serde(rename = "batchSize")]
+    pub batch_size: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoveOpts {
+    #[serde(rename = "writeConcern")]
+    pub write_concern: Option<WriteConcern>,
 }
 
 pub fn parse<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Option<Expr>, String> {
@@ -66,25 +214,106 @@ pub fn parse<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Option<Expr>, String
     trace!("Parse db");
 
     let db = tok.expect_name()?;
-    if db != "db" {
-        return Err("Expected 'db'".into());
+    match &db[..] {
+        "show" => {
+            let oper = parse_show(tok)?;
+            return Ok(Some(Expr::Database { oper }));
+        }
+        "rs" | "sh" => return parse_helper(db, tok).map(Some),
+        "db" => {}
+        _ => return Err("Expected 'db', 'rs' or 'sh'".into()),
     }
 
     trace!("parse collection");
 
     tok.expect_kind(TokenKind::FullStop)?;
-    let collection = tok.expect_name()?;
+    let name = tok.expect_name()?;
+
+    // db.<oper>() as opposed to db.<collection>.<oper>()
+    if tok.peek_kind() == Some(TokenKind::ParenLeft) {
+        let oper = parse_db_oper(name, tok)?;
+        return Ok(Some(Expr::Database { oper }));
+    }
 
     tok.expect_kind(TokenKind::FullStop)?;
 
     let oper = parse_oper(tok)?;
 
-    Ok(Some(Expr { collection, oper }))
+    Ok(Some(Expr::Collection {
+        collection: name,
+        oper,
+    }))
 }
 
-fn parse_oper<B: io::BufRead>(mut tok: &mut Tokens<B>) -> Result<Oper, String> {
+fn parse_show<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<DbOper, String> {
+    trace!("parse_show");
+    tok.skip_white();
+    let what = tok.expect_name()?;
+    match &what[..] {
+        "dbs" | "databases" => Ok(DbOper::ShowDbs),
+        "collections" => Ok(DbOper::ShowCollections),
+        _ => Err(format!("Unhandled show: {}", what)),
+    }
+}
+
+fn parse_helper<B: io::BufRead>(root: String, tok: &mut Tokens<B>) -> Result<Expr, String> {
+    trace!("parse_helper");
+    tok.expect_kind(TokenKind::FullStop)?;
+    let name = tok.expect_name()?;
+    tok.find_pair(TokenKind::ParenLeft, TokenKind::ParenRight, false, false)?;
+
+    let expr = match (&root[..], &name[..]) {
+        ("rs", "status") => Expr::ReplicaSet {
+            oper: RsOper::Status,
+        },
+        ("rs", "conf") | ("rs", "config") => Expr::ReplicaSet { oper: RsOper::Conf },
+        ("rs", "printReplicationInfo") | ("rs", "getReplicationInfo") => Expr::ReplicaSet {
+            oper: RsOper::PrintReplicationInfo,
+        },
+        ("rs", "printSecondaryReplicationInfo") | ("rs", "printSlaveReplicationInfo") => {
+            Expr::ReplicaSet {
+                oper: RsOper::PrintSecondaryReplicationInfo,
+            }
+        }
+        ("sh", "status") => Expr::Sharding {
+            oper: ShOper::Status,
+        },
+        _ => return Err(format!("Unhandled {} operation: {}", root, name)),
+    };
+    Ok(expr)
+}
+
+fn parse_db_oper<B: io::BufRead>(name: String, tok: &mut Tokens<B>) -> Result<DbOper, String> {
+    trace!("parse_db_oper");
+    let mut par_tok = tok.find_pair(TokenKind::ParenLeft, TokenKind::ParenRight, false, false)?;
+
+    match &name[..] {
+        "getCollectionNames" => Ok(DbOper::GetCollectionNames),
+        "getCollectionInfos" => Ok(DbOper::GetCollectionInfos {
+            doc: maybe_expect_doc(&mut par_tok)?,
+        }),
+        "stats" => Ok(DbOper::Stats),
+        "createCollection" => parse_create_collection(par_tok),
+        "createView" => parse_create_view(par_tok),
+        "runCommand" => parse_run_command(par_tok, false),
+        "adminCommand" => parse_run_command(par_tok, true),
+        "currentOp" => Ok(DbOper::CurrentOp {
+            doc: maybe_expect_doc(&mut par_tok)?,
+        }),
+        "startTransaction" => Ok(DbOper::StartTransaction),
+        "commitTransaction" => Ok(DbOper::CommitTransaction),
+        "abortTransaction" => Ok(DbOper::AbortTransaction),
+        "killOp" => parse_kill_op(par_tok),
+        _ => Err(format!("Unhandled db operation: {}", name)),
+    }
+}
+
+fn parse_oper<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Oper, String> {
     trace!("parse_oper");
     let name = tok.expect_name()?;
+    if name == "insert" {
+        return parse_insert_start(tok);
+    }
     let par_tok = tok.find_pair(TokenKind::ParenLeft, TokenKind::ParenRight, false, false)?;
 
     match &name[..] {
@@ -94,7 +323,7 @@ fn parse_oper<B: io::BufRead>(mut tok: &mut Tokens<B>) -> Result<Oper, String> {
             while tok.peek_kind() == Some(TokenKind::FullStop) {
                 tok.expect_kind(TokenKind::FullStop)?;
                 if let Oper::Find { cursor, .. } = &mut oper {
-                    parse_cursor_opt(&mut tok, cursor)?;
+                    parse_cursor_opt(tok, cursor)?;
                 }
             }
             Ok(oper)
@@ -102,8 +331,28 @@ fn parse_oper<B: io::BufRead>(mut tok: &mut Tokens<B>) -> Result<Oper, String> {
         "count" => parse_count(par_tok),
         "distinct" => parse_distinct(par_tok),
         "update" => parse_update(par_tok),
-        "insert" => parse_insert(par_tok),
         "remove" => parse_remove(par_tok),
+        "createIndex" => parse_create_index(par_tok),
+        "createIndexes" => parse_create_indexes(par_tok),
+        "getIndexes" => Ok(Oper::GetIndexes),
+        "dropIndex" => Ok(Oper::DropIndex {
+            index: parse_index_ref(par_tok)?,
+        }),
+        "hideIndex" => Ok(Oper::HideIndex {
+            index: parse_index_ref(par_tok)?,
+            hidden: true,
+        }),
+        "unhideIndex" => Ok(Oper::HideIndex {
+            index: parse_index_ref(par_tok)?,
+            hidden: false,
+        }),
+        "indexStats" => Ok(Oper::IndexStats),
+        "stats" => Ok(Oper::Stats),
+        "checksum" => parse_checksum(par_tok),
+        "aggregate" => parse_aggregate(par_tok),
+        "drop" => Ok(Oper::Drop),
+        "renameCollection" => parse_rename_collection(par_tok),
+        "setValidator" => parse_set_validator(par_tok),
         _ => Err(format!("Unhandled operation: {}", name)),
     }
 }
@@ -166,20 +415,301 @@ fn parse_update<B: io::BufRead>(mut tok: Tokens<B>) -> Result<Oper, String> {
     Ok(Oper::Update { query, upd, uopts })
 }
 
+fn parse_insert_start<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Oper, String> {
+    trace!("parse_insert_start");
+    tok.expect_kind(TokenKind::ParenLeft)?;
+    tok.skip_white();
+    if tok.peek_kind() == Some(TokenKind::BracketLeft) {
+        tok.expect_kind(TokenKind::BracketLeft)?;
+        return Ok(Oper::InsertStream);
+    }
+    let par_tok = tok.find_close(TokenKind::ParenLeft, TokenKind::ParenRight, false)?;
+    parse_insert(par_tok)
+}
+
 fn parse_insert<B: io::BufRead>(mut tok: Tokens<B>) -> Result<Oper, String> {
     trace!("parse_insert");
-    let mut doc = maybe_arr(&mut tok)?;
-    if doc.is_none() {
-        doc = maybe_expect_doc(&mut tok)?;
+    tok.skip_white();
+    if tok.maybe_literal("-") {
+        let iopts = maybe_opts(&mut tok)?;
+        return Ok(Oper::InsertInput { iopts });
     }
-    let doc = doc.ok_or("Insert needs a document")?;
-    Ok(Oper::Insert { doc })
+    let doc = maybe_expect_doc(&mut tok)?.ok_or("Insert needs a document")?;
+    let iopts = maybe_opts(&mut tok)?;
+    Ok(Oper::Insert { doc, iopts })
+}
+
+/// The next document of the array of an `Oper::InsertStream`, or `None` when the closing
+/// `]` is reached.
+pub fn next_array_doc<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Option<String>, String> {
+    tok.skip_white();
+    match tok.peek_kind() {
+        Some(TokenKind::BracketRight) => {
+            tok.next();
+            return Ok(None);
+        }
+        Some(TokenKind::CurlLeft) => {}
+        Some(_) => return Err("Insert expects an array of documents".into()),
+        None => return Err("Unbalanced BracketLeft-BracketRight".into()),
+    }
+    let doc = tok
+        .find_pair(TokenKind::CurlLeft, TokenKind::CurlRight, true, false)?
+        .into_string();
+    tok.skip_white();
+    match tok.peek_kind() {
+        Some(TokenKind::Comma) => {
+            tok.next();
+        }
+        Some(TokenKind::BracketRight) => {}
+        _ => return Err("Expected , or ] after document in array".into()),
+    }
+    Ok(Some(doc))
+}
+
+/// The options after the array of an `Oper::InsertStream`, up to the closing `)`.
+pub fn parse_insert_end<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<InsertOpts, String> {
+    let mut par_tok = tok.find_close(TokenKind::ParenLeft, TokenKind::ParenRight, false)?;
+    par_tok.skip_white();
+    maybe_opts(&mut par_tok)
+}
+
+fn parse_checksum<B: io::BufRead>(mut tok: Tokens<B>) -> Result<Oper, String> {
+    trace!("parse_checksum");
+    let doc = maybe_expect_doc(&mut tok)?;
+    let copts = maybe_opts(&mut tok)?;
+    Ok(Oper::Checksum { doc, copts })
+}
+
+fn parse_aggregate<B: io::BufRead>(mut tok: Tokens<B>) -> Result<Oper, String> {
+    trace!("parse_aggregate");
+    let pipeline = maybe_arr(&mut tok)?.ok_or("aggregate requires a pipeline array")?;
+    let aopts = maybe_opts(&mut tok)?;
+    Ok(Oper::Aggregate { pipeline, aopts })
 }
 
 fn parse_remove<B: io::BufRead>(mut tok: Tokens<B>) -> Result<Oper, String> {
     trace!("parse_remove");
     let doc = maybe_expect_doc(&mut tok)?.ok_or("Remove needs a document")?;
-    Ok(Oper::Remove { doc })
+    let ropts = maybe_opts(&mut tok)?;
+    Ok(Oper::Remove { doc, ropts })
+}
+
+fn parse_create_index<B: io::BufRead>(mut tok: Tokens<B>) -> Result<Oper, String> {
+    trace!("parse_create_index");
+    let keys = maybe_expect_doc(&mut tok)?.ok_or("createIndex requires keys")?;
+    let iopts = maybe_opts_doc(&mut tok)?;
+    Ok(Oper::CreateIndex { keys, iopts })
+}
+
+fn parse_create_indexes<B: io::BufRead>(mut tok: Tokens<B>) -> Result<Oper, String> {
+    trace!("parse_create_indexes");
+    let keys = maybe_arr(&mut tok)?.ok_or("createIndexes requires an array of keys")?;
+    let iopts = maybe_opts_doc(&mut tok)?;
+    Ok(Oper::CreateIndexes { keys, iopts })
+}
+
+/// Optional trailing options document deserialized to `T`.
+fn maybe_opts<B: io::BufRead, T>(tok: &mut Tokens<B>) -> Result<T, String>
+where
+    T: Default + for<'de> Deserialize<'de>,
+{
+    match maybe_opts_doc(tok)? {
+        Some(doc) => json5::from_str(&doc).map_err(|e| e.to_string()),
+        None => Ok(T::default()),
+    }
+}
+
+/// Parses a write concern either as a document `{ w: "majority", j: true, wtimeout: 5000 }`
+/// or the shorthand of only the `w` value, such as `majority` or `1`.
+pub fn parse_write_concern(s: &str) -> Result<WriteConcern, String> {
+    let s = s.trim();
+    if s.starts_with('{') {
+        return json5::from_str(s).map_err(|e| e.to_string());
+    }
+    let w = match s.parse::<u32>() {
+        Ok(n) => Acknowledgment::Nodes(n),
+        Err(_) => Acknowledgment::from(s.to_string()),
+    };
+    Ok(WriteConcern::builder().w(w).build())
+}
+
+#[derive(Deserialize)]
+struct ReadPrefDoc {
+    mode: String,
+    #[serde(flatten)]
+    options: ReadPreferenceOptions,
+}
+
+/// Parses a read preference either as a document
+/// `{ mode: "secondary", tagSets: [{ dc: "east" }], maxStalenessSeconds: 90 }`
+/// or the shorthand of only the mode, such as `secondaryPreferred`.
+pub fn parse_read_preference(s: &str) -> Result<ReadPreference, String> {
+    let s = s.trim();
+    let doc: ReadPrefDoc = if s.starts_with('{') {
+        json5::from_str(s).map_err(|e| e.to_string())?
+    } else {
+        ReadPrefDoc {
+            mode: s.to_string(),
+            options: ReadPreferenceOptions::default(),
+        }
+    };
+    read_preference(&doc.mode, doc.options)
+}
+
+fn read_preference(mode: &str, options: ReadPreferenceOptions) -> Result<ReadPreference, String> {
+    let pref = match mode {
+        "primary" => {
+            if options != ReadPreferenceOptions::default() {
+                return Err("Read preference primary can't have tag sets or max staleness".into());
+            }
+            ReadPreference::Primary
+        }
+        "primaryPreferred" => ReadPreference::PrimaryPreferred { options },
+        "secondary" => ReadPreference::Secondary { options },
+        "secondaryPreferred" => ReadPreference::SecondaryPreferred { options },
+        "nearest" => ReadPreference::Nearest { options },
+        _ => return Err(format!("Unknown read preference mode: {}", mode)),
+    };
+    Ok(pref)
+}
+
+/// Parses a duration such as `500ms`, `30s`, `2m` or `1h`. A plain number is seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: u64 = num
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", s))?;
+    let dur = match unit.trim() {
+        "ms" => Duration::from_millis(num),
+        "" | "s" => Duration::from_secs(num),
+        "m" => Duration::from_secs(num * 60),
+        "h" => Duration::from_secs(num * 3600),
+        _ => return Err(format!("Invalid duration unit: {}", s)),
+    };
+    Ok(dur)
+}
+
+/// Parses a read concern level such as `majority` or `snapshot`.
+pub fn parse_read_concern(s: &str) -> Result<ReadConcern, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("Empty read concern".into());
+    }
+    Ok(ReadConcern::custom(s.to_string()))
+}
+
+fn maybe_opts_arr<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Option<String>, String> {
+    if tok.peek_kind() == Some(TokenKind::Comma) {
+        tok.expect_kind(TokenKind::Comma)?;
+        maybe_arr(tok)
+    } else {
+        Ok(None)
+    }
+}
+
+fn maybe_opts_doc<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Option<String>, String> {
+    if tok.peek_kind() == Some(TokenKind::Comma) {
+        tok.expect_kind(TokenKind::Comma)?;
+        maybe_expect_doc(tok)
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_index_ref<B: io::BufRead>(mut tok: Tokens<B>) -> Result<IndexRef, String> {
+    trace!("parse_index_ref");
+    tok.skip_white();
+    let is_string = tok.peek_kind().map(|k| k.is_string_start()) == Some(true);
+    if is_string {
+        Ok(IndexRef::Name(tok.expect_string(false)?))
+    } else {
+        let keys = maybe_expect_doc(&mut tok)?.ok_or("Expected index name or keys")?;
+        Ok(IndexRef::Keys(keys))
+    }
+}
+
+fn parse_create_collection<B: io::BufRead>(mut tok: Tokens<B>) -> Result<DbOper, String> {
+    trace!("parse_create_collection");
+    tok.skip_white();
+    let name = tok.expect_string(false)?;
+    tok.skip_white();
+    let copts = maybe_opts_doc(&mut tok)?;
+    Ok(DbOper::CreateCollection { name, copts })
+}
+
+fn parse_create_view<B: io::BufRead>(mut tok: Tokens<B>) -> Result<DbOper, String> {
+    trace!("parse_create_view");
+    tok.skip_white();
+    let name = tok.expect_string(false)?;
+    tok.skip_white();
+    tok.expect_kind(TokenKind::Comma)?;
+    tok.skip_white();
+    let source = tok.expect_string(false)?;
+    tok.skip_white();
+    tok.expect_kind(TokenKind::Comma)?;
+    let pipeline = maybe_arr(&mut tok)?.ok_or("createView requires a pipeline")?;
+    Ok(DbOper::CreateView {
+        name,
+        source,
+        pipeline,
+    })
+}
+
+fn parse_run_command<B: io::BufRead>(mut tok: Tokens<B>, admin: bool) -> Result<DbOper, String> {
+    trace!("parse_run_command");
+    tok.skip_white();
+    let is_string = tok.peek_kind().map(|k| k.is_string_start()) == Some(true);
+    let cmd = if is_string {
+        // db.runCommand("ping") is short for db.runCommand({ ping: 1 })
+        let name = tok.expect_string(false)?;
+        doc! { name: 1 }
+    } else {
+        let doc = maybe_expect_doc(&mut tok)?.ok_or("runCommand requires a command document")?;
+        crate::decode_bson(&doc).map_err(|e| e.to_string())?
+    };
+    Ok(DbOper::RunCommand { cmd, admin })
+}
+
+/// The opid is a number, or a string like "shard01:1234" for sharded clusters.
+fn parse_kill_op<B: io::BufRead>(mut tok: Tokens<B>) -> Result<DbOper, String> {
+    trace!("parse_kill_op");
+    tok.skip_white();
+    let op = match tok.peek_kind() {
+        None => return Err("killOp requires an opid".into()),
+        Some(k) if k.is_string_start() => Bson::String(tok.expect_string(false)?),
+        Some(_) => Bson::Int64(
+            tok.expect_as()
+                .map_err(|e| format!("killOp expects a number or a string opid: {}", e))?,
+        ),
+    };
+    tok.skip_white();
+    if tok.peek_kind().is_some() {
+        return Err("killOp takes only an opid".into());
+    }
+    Ok(DbOper::KillOp { op })
+}
+
+fn parse_rename_collection<B: io::BufRead>(mut tok: Tokens<B>) -> Result<Oper, String> {
+    trace!("parse_rename_collection");
+    tok.skip_white();
+    let to = tok.expect_string(false)?;
+    tok.skip_white();
+    let mut drop_target = false;
+    if tok.peek_kind() == Some(TokenKind::Comma) {
+        tok.expect_kind(TokenKind::Comma)?;
+        tok.skip_white();
+        drop_target = tok.expect_as()?;
+    }
+    Ok(Oper::RenameCollection { to, drop_target })
+}
+
+fn parse_set_validator<B: io::BufRead>(mut tok: Tokens<B>) -> Result<Oper, String> {
+    trace!("parse_set_validator");
+    let doc = maybe_expect_doc(&mut tok)?.ok_or("setValidator requires a validator")?;
+    let vopts = maybe_opts_doc(&mut tok)?;
+    Ok(Oper::SetValidator { doc, vopts })
 }
 
 fn parse_cursor_opt<B: io::BufRead>(
@@ -206,13 +736,45 @@ fn parse_cursor_opt<B: io::BufRead>(
                 return Err("Expected doc for sort()".into());
             }
         }
+        "readPref" => {
+            let mode = par_tok.expect_string(false)?;
+            par_tok.skip_white();
+            let tag_sets = match maybe_opts_arr(&mut par_tok)? {
+                Some(arr) => Some(json5::from_str(&arr).map_err(|e| e.to_string())?),
+                None => None,
+            };
+            let options = ReadPreferenceOptions::builder().tag_sets(tag_sets).build();
+            opts.read_pref = Some(read_preference(&mode, options)?);
+        }
         _ => return Err(format!("Unrecognized cursor option: {}", name)),
     }
     Ok(())
 }
 
+/// Reads the file of an `@path` reference, which stands in for a document or array literal.
+/// Relative paths are relative to the directory of the script file, if read with -f, or
+/// else the working directory.
+fn maybe_file_ref<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Option<String>, String> {
+    let path = match tok.maybe_path_ref()? {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    let path = match tok.base_dir() {
+        Some(dir) => dir.join(path),
+        None => PathBuf::from(path),
+    };
+    debug!("Read file: {}", path.display());
+    let content =
+        std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    tok.skip_white();
+    Ok(Some(content))
+}
+
 fn maybe_expect_doc<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Option<String>, String> {
     tok.skip_white();
+    if let Some(doc) = maybe_file_ref(tok)? {
+        return Ok(Some(doc));
+    }
     if tok.peek_kind().is_some() {
         let c_tok = tok.find_pair(TokenKind::CurlLeft, TokenKind::CurlRight, true, false)?;
         let doc = Some(c_tok.into_string());
@@ -225,6 +787,9 @@ fn maybe_expect_doc<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Option<String
 
 fn maybe_arr<B: io::BufRead>(tok: &mut Tokens<B>) -> Result<Option<String>, String> {
     tok.skip_white();
+    if let Some(arr) = maybe_file_ref(tok)? {
+        return Ok(Some(arr));
+    }
     if tok.peek_kind() == Some(TokenKind::BracketLeft) {
         let c_tok = tok.find_pair(TokenKind::BracketLeft, TokenKind::BracketRight, true, false)?;
         let doc = Some(c_tok.into_string());
@@ -240,3 +805,383 @@ impl fmt::Display for Expr {
         write!(f, "{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token::tokenize_str;
+
+    fn parse_str(s: &str) -> Expr {
+        parse(&mut tokenize_str(s)).unwrap().unwrap()
+    }
+
+    fn parse_coll(s: &str) -> (String, Oper) {
+        match parse_str(s) {
+            Expr::Collection { collection, oper } => (collection, oper),
+            e => panic!("Expected collection expression: {}", e),
+        }
+    }
+
+    fn parse_db(s: &str) -> DbOper {
+        match parse_str(s) {
+            Expr::Database { oper } => oper,
+            e => panic!("Expected database expression: {}", e),
+        }
+    }
+
+    #[test]
+    fn create_index_with_opts() {
+        let (collection, oper) =
+            parse_coll("db.users.createIndex({ b: 1, a: -1 }, { unique: true })");
+        assert_eq!(collection, "users");
+        match oper {
+            Oper::CreateIndex { keys, iopts } => {
+                assert_eq!(keys, "{ b: 1, a: -1 }");
+                assert_eq!(iopts.as_deref(), Some("{ unique: true }"));
+            }
+            _ => panic!("Expected CreateIndex"),
+        }
+    }
+
+    #[test]
+    fn drop_index_by_name_or_keys() {
+        let (_, oper) = parse_coll("db.users.dropIndex('age_1')");
+        match oper {
+            Oper::DropIndex {
+                index: IndexRef::Name(n),
+            } => assert_eq!(n, "age_1"),
+            _ => panic!("Expected DropIndex by name"),
+        }
+        let (_, oper) = parse_coll("db.users.dropIndex({ age: 1 })");
+        match oper {
+            Oper::DropIndex {
+                index: IndexRef::Keys(k),
+            } => assert_eq!(k, "{ age: 1 }"),
+            _ => panic!("Expected DropIndex by keys"),
+        }
+    }
+
+    #[test]
+    fn db_and_show_opers() {
+        let mut tok = tokenize_str("show dbs db.getCollectionNames() db.users.stats()");
+        match parse(&mut tok).unwrap() {
+            Some(Expr::Database {
+                oper: DbOper::ShowDbs,
+            }) => {}
+            e => panic!("Expected show dbs: {:?}", e),
+        }
+        match parse(&mut tok).unwrap() {
+            Some(Expr::Database {
+                oper: DbOper::GetCollectionNames,
+            }) => {}
+            e => panic!("Expected getCollectionNames: {:?}", e),
+        }
+        match parse(&mut tok).unwrap() {
+            Some(Expr::Collection {
+                oper: Oper::Stats, ..
+            }) => {}
+            e => panic!("Expected stats: {:?}", e),
+        }
+        assert!(parse(&mut tok).unwrap().is_none());
+    }
+
+    #[test]
+    fn collection_lifecycle() {
+        match parse_str("db.createView('active', 'users', [{ $match: { active: true } }])") {
+            Expr::Database {
+                oper:
+                    DbOper::CreateView {
+                        name,
+                        source,
+                        pipeline,
+                    },
+            } => {
+                assert_eq!(name, "active");
+                assert_eq!(source, "users");
+                assert_eq!(pipeline, "[{ $match: { active: true } }]");
+            }
+            e => panic!("Expected createView: {:?}", e),
+        }
+        match parse_coll("db.staging.renameCollection('users', true)").1 {
+            Oper::RenameCollection { to, drop_target } => {
+                assert_eq!(to, "users");
+                assert!(drop_target);
+            }
+            o => panic!("Expected renameCollection: {:?}", o),
+        }
+    }
+
+    #[test]
+    fn run_command() {
+        match parse_db("db.runCommand({ collStats: 'users', scale: 1024 })") {
+            DbOper::RunCommand { cmd, admin } => {
+                assert_eq!(cmd, doc! { "collStats": "users", "scale": 1024_i64 });
+                assert!(!admin);
+            }
+            o => panic!("Expected runCommand: {:?}", o),
+        }
+        match parse_db("db.adminCommand('listDatabases')") {
+            DbOper::RunCommand { cmd, admin } => {
+                assert_eq!(cmd, doc! { "listDatabases": 1 });
+                assert!(admin);
+            }
+            o => panic!("Expected adminCommand: {:?}", o),
+        }
+        // the name is taken as is, not spliced into JSON.
+        match parse_db(r#"db.runCommand('a", "b\\')"#) {
+            DbOper::RunCommand { cmd, .. } => assert_eq!(cmd, doc! { r#"a", "b\\"#: 1 }),
+            o => panic!("Expected runCommand: {:?}", o),
+        }
+        let mut tok = tokenize_str("db.runCommand()");
+        assert!(parse(&mut tok).is_err());
+    }
+
+    #[test]
+    fn current_and_kill_op() {
+        match parse_db("db.currentOp({ secs_running: { $gte: 5 } })") {
+            DbOper::CurrentOp { doc } => {
+                assert_eq!(doc.as_deref(), Some("{ secs_running: { $gte: 5 } }"))
+            }
+            o => panic!("Expected currentOp: {:?}", o),
+        }
+        match parse_db("db.currentOp()") {
+            DbOper::CurrentOp { doc } => assert!(doc.is_none()),
+            o => panic!("Expected currentOp: {:?}", o),
+        }
+        match parse_db("db.killOp( 1234 )") {
+            DbOper::KillOp { op } => assert_eq!(op, Bson::Int64(1234)),
+            o => panic!("Expected killOp: {:?}", o),
+        }
+        match parse_db("db.killOp('shard01:1234')") {
+            DbOper::KillOp { op } => assert_eq!(op, Bson::String("shard01:1234".into())),
+            o => panic!("Expected killOp: {:?}", o),
+        }
+        for s in &[
+            "db.killOp()",
+            "db.killOp(abc)",
+            "db.killOp({ $where: 'x' })",
+            "db.killOp(1, 2)",
+        ] {
+            assert!(parse(&mut tokenize_str(s)).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn write_concern() {
+        let wc = parse_write_concern("{ w: 'majority', j: true, wtimeout: 5000 }").unwrap();
+        assert_eq!(wc.w, Some(Acknowledgment::Majority));
+        assert_eq!(wc.journal, Some(true));
+        assert_eq!(wc.w_timeout, Some(std::time::Duration::from_millis(5000)));
+        let wc = parse_write_concern("1").unwrap();
+        assert_eq!(wc.w, Some(Acknowledgment::Nodes(1)));
+        match parse_coll("db.users.insert({ a: 1 }, { writeConcern: { w: 1 } })").1 {
+            Oper::Insert { iopts, .. } => {
+                assert_eq!(
+                    iopts.write_concern.unwrap().w,
+                    Some(Acknowledgment::Nodes(1))
+                )
+            }
+            o => panic!("Expected insert: {:?}", o),
+        }
+        // remove always removes every match, so a justOne must not be silently ignored.
+        let mut tok = tokenize_str("db.users.remove({ a: 1 }, { justOne: true })");
+        assert!(parse(&mut tok).is_err());
+        // nor a misspelled option, which would drop the write concern without a word.
+        let mut tok = tokenize_str("db.users.insert({ a: 1 }, { writeconcern: { w: 1 } })");
+        assert!(parse(&mut tok).is_err());
+        let mut tok =
+            tokenize_str("db.users.update({ a: 1 }, { $set: { b: 1 } }, { mutli: true })");
+        assert!(parse(&mut tok).is_err());
+        match parse_coll("db.users.update({}, { $set: { b: 1 } }, { multi: true, upsert: true })").1
+        {
+            Oper::Update { uopts, .. } => {
+                assert_eq!(uopts.multi, Some(true));
+                assert_eq!(uopts.upsert, Some(true));
+            }
+            o => panic!("Expected update: {:?}", o),
+        }
+    }
+
+    #[test]
+    fn read_preference() {
+        let pref = parse_read_preference("secondaryPreferred").unwrap();
+        assert!(matches!(pref, ReadPreference::SecondaryPreferred { .. }));
+        let pref = parse_read_preference(
+            "{ mode: 'secondary', tagSets: [{ dc: 'east' }], maxStalenessSeconds: 90 }",
+        )
+        .unwrap();
+        match pref {
+            ReadPreference::Secondary { options } => {
+                let tags = options.tag_sets.unwrap();
+                assert_eq!(tags[0].get("dc").map(|s| &s[..]), Some("east"));
+                assert_eq!(
+                    options.max_staleness,
+                    Some(std::time::Duration::from_secs(90))
+                );
+            }
+            p => panic!("Expected secondary: {:?}", p),
+        }
+        assert!(parse_read_preference("{ mode: 'primary', maxStalenessSeconds: 90 }").is_err());
+        match parse_coll("db.users.find().readPref('nearest', [{ dc: 'west' }]).limit(1)").1 {
+            Oper::Find { cursor, .. } => {
+                assert!(matches!(
+                    cursor.read_pref,
+                    Some(ReadPreference::Nearest { .. })
+                ));
+                assert_eq!(cursor.limit, Some(1));
+            }
+            o => panic!("Expected find: {:?}", o),
+        }
+        match parse_coll("db.users.find().readPref('primary')").1 {
+            Oper::Find { cursor, .. } => {
+                assert!(matches!(cursor.read_pref, Some(ReadPreference::Primary)))
+            }
+            o => panic!("Expected find: {:?}", o),
+        }
+        let mut tok = tokenize_str("db.users.find().readPref('primary', [{ dc: 'west' }])");
+        assert!(parse(&mut tok).is_err());
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_duration("2d").is_err());
+        assert!(parse_duration("s").is_err());
+    }
+
+    #[test]
+    fn helpers() {
+        assert!(matches!(
+            parse_str("rs.status()"),
+            Expr::ReplicaSet {
+                oper: RsOper::Status
+            }
+        ));
+        assert!(matches!(
+            parse_str("rs.printSecondaryReplicationInfo()"),
+            Expr::ReplicaSet {
+                oper: RsOper::PrintSecondaryReplicationInfo
+            }
+        ));
+        assert!(matches!(
+            parse_str("sh.status()"),
+            Expr::Sharding {
+                oper: ShOper::Status
+            }
+        ));
+        let mut tok = tokenize_str("rs.initiate()");
+        assert!(parse(&mut tok).is_err());
+        let mut tok = tokenize_str("foo.bar.find()");
+        assert!(parse(&mut tok).is_err());
+    }
+
+    #[test]
+    fn insert_stream() {
+        let mut tok =
+            tokenize_str("db.users.insert([{ a: 1 }, { b: [2, 3] },], { ordered: false }) x");
+        match parse(&mut tok).unwrap().unwrap() {
+            Expr::Collection {
+                oper: Oper::InsertStream,
+                ..
+            } => {}
+            e => panic!("Expected insert stream: {}", e),
+        }
+        assert_eq!(next_array_doc(&mut tok).unwrap().unwrap(), "{ a: 1 }");
+        assert_eq!(next_array_doc(&mut tok).unwrap().unwrap(), "{ b: [2, 3] }");
+        assert!(next_array_doc(&mut tok).unwrap().is_none());
+        assert_eq!(parse_insert_end(&mut tok).unwrap().ordered, Some(false));
+        assert_eq!(tok.into_string(), " x");
+
+        let mut tok = tokenize_str("[1, 2]");
+        tok.expect_kind(TokenKind::BracketLeft).unwrap();
+        assert!(next_array_doc(&mut tok).is_err());
+        let mut tok = tokenize_str("[{ a: 1 } { b: 2 }]");
+        tok.expect_kind(TokenKind::BracketLeft).unwrap();
+        assert!(next_array_doc(&mut tok).is_err());
+    }
+
+    #[test]
+    fn file_ref() {
+        let path = std::env::temp_dir().join(format!("mung-query-{}.json5", std::process::id()));
+        std::fs::write(&path, "{ name: 'martin' }\n").unwrap();
+        let cmd = format!("db.users.find(@{}, @{})", path.display(), path.display());
+        match parse_coll(&cmd).1 {
+            Oper::Find { doc, proj, .. } => {
+                assert_eq!(doc.as_deref(), Some("{ name: 'martin' }\n"));
+                assert_eq!(proj.as_deref(), Some("{ name: 'martin' }\n"));
+            }
+            o => panic!("Expected find: {:?}", o),
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let mut tok = tokenize_str("db.users.find(@/no/such/file.json)");
+        assert!(parse(&mut tok).is_err());
+    }
+
+    #[test]
+    fn file_ref_in_script_dir() {
+        let dir = std::env::temp_dir().join(format!("mung-script-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("pipeline.json"), "[{ $match: { a: 1 } }]").unwrap();
+        let mut tok = tokenize_str("db.users.aggregate(@pipeline.json, { allowDiskUse: true })")
+            .with_base_dir(&dir);
+        match parse(&mut tok).unwrap().unwrap() {
+            Expr::Collection {
+                oper: Oper::Aggregate { pipeline, aopts },
+                ..
+            } => {
+                assert_eq!(pipeline, "[{ $match: { a: 1 } }]");
+                assert_eq!(aopts.allow_disk_use, Some(true));
+            }
+            e => panic!("Expected aggregate: {}", e),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn aggregate() {
+        match parse_coll("db.users.aggregate([{ $group: { _id: '$age' } }])").1 {
+            Oper::Aggregate { pipeline, aopts } => {
+                assert_eq!(pipeline, "[{ $group: { _id: '$age' } }]");
+                assert!(aopts.batch_size.is_none());
+            }
+            o => panic!("Expected aggregate: {:?}", o),
+        }
+        let mut tok = tokenize_str("db.users.aggregate()");
+        assert!(parse(&mut tok).is_err());
+        let mut tok = tokenize_str("db.users.aggregate([], { nope: 1 })");
+        assert!(parse(&mut tok).is_err());
+    }
+
+    #[test]
+    fn checksum() {
+        match parse_coll("db.users.checksum({ active: true }, { fields: ['name', 'a.b'] })").1 {
+            Oper::Checksum { doc, copts } => {
+                assert_eq!(doc.as_deref(), Some("{ active: true }"));
+                assert_eq!(copts.fields.unwrap(), vec!["name", "a.b"]);
+            }
+            o => panic!("Expected checksum: {:?}", o),
+        }
+        assert!(matches!(
+            parse_coll("db.users.checksum()").1,
+            Oper::Checksum { doc: None, .. }
+        ));
+        let mut tok = tokenize_str("db.users.checksum({}, { nope: 1 })");
+        assert!(parse(&mut tok).is_err());
+    }
+
+    #[test]
+    fn insert_input() {
+        match parse_coll("db.users.insert(-, { ordered: false })").1 {
+            Oper::InsertInput { iopts } => assert_eq!(iopts.ordered, Some(false)),
+            o => panic!("Expected insert input: {:?}", o),
+        }
+        assert!(matches!(
+            parse_coll("db.users.insert( - )").1,
+            Oper::InsertInput { .. }
+        ));
+    }
+}
@@ -3,6 +3,8 @@ use std::fmt;
 use std::io;
 use std::iter::Iterator;
 use std::iter::Peekable;
+use std::path::Path;
+use std::rc::Rc;
 use std::vec::IntoIter;
 
 #[derive(Clone)]
@@ -101,16 +103,17 @@ impl<B: io::BufRead> Iterator for Tokenizer<B> {
     }
 }
 
+/// Tokens, with the directory that relative `@path` references are resolved against.
 pub enum Tokens<B: io::BufRead> {
-    Tokenizer(Tokenizer<B>),
-    Peekable(Peekable<IntoIter<Token>>),
+    Tokenizer(Tokenizer<B>, Option<Rc<Path>>),
+    Peekable(Peekable<IntoIter<Token>>, Option<Rc<Path>>),
 }
 
 impl<B: io::BufRead> Tokens<B> {
     pub fn peek(&mut self) -> Option<&Token> {
         match self {
-            Tokens::Tokenizer(t) => t.peek(),
-            Tokens::Peekable(t) => t.peek(),
+            Tokens::Tokenizer(t, _) => t.peek(),
+            Tokens::Peekable(t, _) => t.peek(),
         }
     }
 }
@@ -119,13 +122,29 @@ impl<B: io::BufRead> Iterator for Tokens<B> {
     type Item = Token;
     fn next(&mut self) -> Option<Self::Item> {
         match self {
-            Tokens::Tokenizer(t) => t.next(),
-            Tokens::Peekable(t) => t.next(),
+            Tokens::Tokenizer(t, _) => t.next(),
+            Tokens::Peekable(t, _) => t.next(),
         }
     }
 }
 
 impl<B: io::BufRead> Tokens<B> {
+    /// Resolves relative `@path` references against `dir` instead of the working directory,
+    /// such as the directory of a script file.
+    pub fn with_base_dir(self, dir: &Path) -> Self {
+        let dir = Some(Rc::from(dir));
+        match self {
+            Tokens::Tokenizer(t, _) => Tokens::Tokenizer(t, dir),
+            Tokens::Peekable(t, _) => Tokens::Peekable(t, dir),
+        }
+    }
+
+    pub fn base_dir(&self) -> Option<&Path> {
+        match self {
+            Tokens::Tokenizer(_, dir) | Tokens::Peekable(_, dir) => dir.as_deref(),
+        }
+    }
+
     fn into_vec(self) -> Vec<Token> {
         self.collect()
     }
@@ -150,6 +169,44 @@ impl<B: io::BufRead> Tokens<B> {
         }
     }
 
+    /// Consumes the next token if it is exactly `s`.
+    pub fn maybe_literal(&mut self, s: &str) -> bool {
+        if self.peek().map(|t| t.s == s).unwrap_or(false) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes an `@path` reference, like `@users.json` or `@"my dir/users.json"`, and
+    /// gives the path.
+    pub fn maybe_path_ref(&mut self) -> Result<Option<String>, String> {
+        let is_ref = self
+            .peek()
+            .map(|t| t.kind == TokenKind::Other && t.s.starts_with('@'))
+            .unwrap_or(false);
+        if !is_ref {
+            return Ok(None);
+        }
+        let mut path = self.expect_something()?.s.split_off(1);
+        if path.is_empty() && self.peek_kind().map(|k| k.is_string_start()) == Some(true) {
+            return self.expect_string(false).map(Some);
+        }
+        // a path runs until whitespace, a comma or a closing paren.
+        while let Some(t) = self.peek() {
+            if !matches!(t.kind, TokenKind::Other | TokenKind::FullStop) {
+                break;
+            }
+            path.push_str(&t.s);
+            self.next();
+        }
+        if path.is_empty() {
+            return Err("Expected a path after @".into());
+        }
+        Ok(Some(path))
+    }
+
     fn expect_something(&mut self) -> Result<Token, String> {
         if let Some(x) = self.next() {
             Ok(x)
@@ -197,6 +254,28 @@ impl<B: io::BufRead> Tokens<B> {
         if keep {
             into.push(stok);
         }
+        self.collect_pair(start, end, into, keep, use_string_escape)
+    }
+
+    /// Like `find_pair`, when the `start` token has already been consumed. The `end` token
+    /// is not included.
+    pub fn find_close(
+        &mut self,
+        start: TokenKind,
+        end: TokenKind,
+        use_string_escape: bool,
+    ) -> Result<Tokens<B>, String> {
+        self.collect_pair(start, end, vec![], false, use_string_escape)
+    }
+
+    fn collect_pair(
+        &mut self,
+        start: TokenKind,
+        end: TokenKind,
+        mut into: Vec<Token>,
+        keep: bool,
+        use_string_escape: bool,
+    ) -> Result<Tokens<B>, String> {
         let mut level = 1;
         loop {
             // we might want to consume a string
@@ -244,7 +323,10 @@ impl<B: io::BufRead> Tokens<B> {
             return Err(format!("Unbalanced {:?}-{:?}", start, end));
         }
         trace!("find_pair: {:?} {:?} {:?}", start, into, end);
-        Ok(Tokens::Peekable(into.into_iter().peekable()))
+        let dir = match self {
+            Tokens::Tokenizer(_, dir) | Tokens::Peekable(_, dir) => dir.clone(),
+        };
+        Ok(Tokens::Peekable(into.into_iter().peekable(), dir))
     }
 }
 
@@ -290,22 +372,16 @@ impl TokenKind {
     }
 
     fn is_segment(self) -> bool {
-        match self {
-            TokenKind::Whitespace | TokenKind::Other => true,
-            _ => false,
-        }
+        matches!(self, TokenKind::Whitespace | TokenKind::Other)
     }
 
-    fn is_string_start(self) -> bool {
-        match self {
-            TokenKind::SingleQuote | TokenKind::DoubleQuote => true,
-            _ => false,
-        }
+    pub fn is_string_start(self) -> bool {
+        matches!(self, TokenKind::SingleQuote | TokenKind::DoubleQuote)
     }
 }
 
 pub fn tokenize<B: io::BufRead>(read: B) -> Tokens<B> {
-    Tokens::Tokenizer(Tokenizer(CharIter(read).peekable(), None))
+    Tokens::Tokenizer(Tokenizer(CharIter(read).peekable(), None), None)
 }
 
 pub fn tokenize_str(s: &str) -> Tokens<io::BufReader<io::Cursor<&[u8]>>> {
@@ -375,6 +451,34 @@ mod test {
         );
     }
 
+    #[test]
+    fn find_close_after_start() {
+        let mut tok = tokenize_str("(a, (b)) c");
+        tok.expect_kind(TokenKind::ParenLeft).unwrap();
+        let x = tok
+            .find_close(TokenKind::ParenLeft, TokenKind::ParenRight, false)
+            .unwrap();
+        assert_eq!(x.into_string(), "a, (b)");
+        assert_eq!(tok.into_string(), " c");
+    }
+
+    #[test]
+    fn path_ref() {
+        let mut tok = tokenize_str("@../data/users.json, @\"my dir/q.json\"");
+        assert_eq!(
+            tok.maybe_path_ref().unwrap().as_deref(),
+            Some("../data/users.json")
+        );
+        tok.expect_kind(TokenKind::Comma).unwrap();
+        tok.skip_white();
+        assert_eq!(
+            tok.maybe_path_ref().unwrap().as_deref(),
+            Some("my dir/q.json")
+        );
+        assert!(tokenize_str("{}").maybe_path_ref().unwrap().is_none());
+        assert!(tokenize_str("@ x").maybe_path_ref().is_err());
+    }
+
     #[test]
     fn string_with_escape() {
         let mut tok = tokenize_str("' \\' '");